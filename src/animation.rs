@@ -0,0 +1,452 @@
+use crate::color::Gradient;
+use crate::error::Error;
+use crate::knob_device::KnobDevice;
+use crate::{Color, Event, KeyEvent, Knob, Message, Profile};
+use std::f32::consts::PI;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// One of the LEDs a profile assigns a color to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Led {
+    Primary,
+    Secondary,
+    Pointer,
+    /// Idle color of button 0 to 3 (A to D)
+    ButtonIdle(u8),
+    /// Pressed color of button 0 to 3 (A to D)
+    ButtonPress(u8),
+}
+
+impl Led {
+    /// Every LED of a profile.
+    pub const ALL: [Led; 11] = [
+        Led::Primary,
+        Led::Secondary,
+        Led::Pointer,
+        Led::ButtonIdle(0),
+        Led::ButtonIdle(1),
+        Led::ButtonIdle(2),
+        Led::ButtonIdle(3),
+        Led::ButtonPress(0),
+        Led::ButtonPress(1),
+        Led::ButtonPress(2),
+        Led::ButtonPress(3),
+    ];
+
+    fn index(&self) -> Option<usize> {
+        match *self {
+            Led::Primary => Some(0),
+            Led::Secondary => Some(1),
+            Led::Pointer => Some(2),
+            Led::ButtonIdle(n) if n < 4 => Some(3 + n as usize),
+            Led::ButtonPress(n) if n < 4 => Some(7 + n as usize),
+            _ => None,
+        }
+    }
+}
+
+/// Colors produced by the layers for a single frame. LEDs no layer touched
+/// are left as they are on the device.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LedFrame {
+    colors: [Option<Color>; 11],
+}
+
+impl LedFrame {
+    pub fn get(&self, led: Led) -> Option<Color> {
+        led.index().and_then(|i| self.colors[i])
+    }
+
+    pub fn set(&mut self, led: Led, color: Color) {
+        if let Some(i) = led.index() {
+            self.colors[i] = Some(color);
+        }
+    }
+
+    /// Mix `color` over what lower layers drew, `amount` from 0.0 to 1.0.
+    /// LEDs nothing has drawn yet count as black.
+    pub fn blend(&mut self, led: Led, color: Color, amount: f32) {
+        let base = self.get(led).unwrap_or(Color::new(0, 0, 0));
+        self.set(led, base.lerp(&color, amount));
+    }
+
+    /// Profile update holding only the LEDs that differ from `previous`.
+    fn changes(&self, previous: &LedFrame) -> Option<Profile> {
+        let mut profile = Profile::default();
+        let mut changed = false;
+        for led in Led::ALL {
+            let color = self.get(led);
+            if color.is_some() && color != previous.get(led) {
+                changed = true;
+                let field = match led {
+                    Led::Primary => &mut profile.primary,
+                    Led::Secondary => &mut profile.secondary,
+                    Led::Pointer => &mut profile.pointer,
+                    Led::ButtonIdle(0) => &mut profile.button_a_idle,
+                    Led::ButtonIdle(1) => &mut profile.button_b_idle,
+                    Led::ButtonIdle(2) => &mut profile.button_c_idle,
+                    Led::ButtonIdle(_) => &mut profile.button_d_idle,
+                    Led::ButtonPress(0) => &mut profile.button_a_press,
+                    Led::ButtonPress(1) => &mut profile.button_b_press,
+                    Led::ButtonPress(2) => &mut profile.button_c_press,
+                    Led::ButtonPress(_) => &mut profile.button_d_press,
+                };
+                *field = color;
+            }
+        }
+        changed.then_some(profile)
+    }
+}
+
+/// Something drawn by the [`Animator`]. Layers are drawn in the order they
+/// were added, so later layers end up on top.
+pub trait Layer: Send {
+    /// Draw the frame at `elapsed` since the animator started.
+    fn render(&mut self, elapsed: Duration, frame: &mut LedFrame);
+
+    /// React to a message from the device, received at `elapsed`.
+    fn handle(&mut self, _message: &Message, _elapsed: Duration) {}
+}
+
+/// Fades `color` in and out once per `period`.
+#[derive(Debug, Clone)]
+pub struct Breathing {
+    pub leds: Vec<Led>,
+    pub color: Color,
+    pub period: Duration,
+}
+
+impl Layer for Breathing {
+    fn render(&mut self, elapsed: Duration, frame: &mut LedFrame) {
+        let phase = elapsed.as_secs_f32() / self.period.as_secs_f32().max(0.001);
+        let level = 0.5 - 0.5 * (phase * 2.0 * PI).cos();
+        for led in &self.leds {
+            frame.set(*led, self.color.scale(level));
+        }
+    }
+}
+
+/// Cycles through the hue wheel once per `period`, spreading the LEDs
+/// evenly around it.
+#[derive(Debug, Clone)]
+pub struct Rainbow {
+    pub leds: Vec<Led>,
+    pub period: Duration,
+    pub saturation: f32,
+    pub value: f32,
+}
+
+impl Layer for Rainbow {
+    fn render(&mut self, elapsed: Duration, frame: &mut LedFrame) {
+        let phase = elapsed.as_secs_f32() / self.period.as_secs_f32().max(0.001);
+        let spread = 360.0 / self.leds.len().max(1) as f32;
+        for (i, led) in self.leds.iter().enumerate() {
+            let hue = phase * 360.0 + i as f32 * spread;
+            frame.set(*led, Color::from_hsv(hue, self.saturation, self.value));
+        }
+    }
+}
+
+/// Flashes a button in `color` when it is pressed, fading out over `decay`.
+#[derive(Debug, Clone)]
+pub struct KeyPulse {
+    pub color: Color,
+    pub decay: Duration,
+    pressed: [Option<Duration>; 4],
+}
+
+impl KeyPulse {
+    pub fn new(color: Color, decay: Duration) -> Self {
+        KeyPulse {
+            color,
+            decay,
+            pressed: [None; 4],
+        }
+    }
+}
+
+impl Layer for KeyPulse {
+    fn render(&mut self, elapsed: Duration, frame: &mut LedFrame) {
+        for (id, pressed) in self.pressed.iter_mut().enumerate() {
+            let Some(at) = *pressed else {
+                continue;
+            };
+            let age = elapsed.saturating_sub(at).as_secs_f32();
+            let amount = 1.0 - age / self.decay.as_secs_f32().max(0.001);
+            if amount <= 0.0 {
+                // faded out, leave whatever is underneath
+                *pressed = None;
+                continue;
+            }
+            frame.blend(Led::ButtonIdle(id as u8), self.color, amount);
+        }
+    }
+
+    fn handle(&mut self, message: &Message, elapsed: Duration) {
+        if let Message::Event(Event::Key(KeyEvent::Down { id, .. })) = message {
+            if let Some(pressed) = self.pressed.get_mut(*id as usize) {
+                *pressed = Some(elapsed);
+            }
+        }
+    }
+}
+
+/// Colors LEDs along a gradient according to the knob position.
+#[derive(Debug, Clone)]
+pub struct FollowKnob {
+    pub leds: Vec<Led>,
+    pub gradient: Gradient,
+    range: (u64, u64),
+    position: Option<u64>,
+}
+
+impl FollowKnob {
+    /// Map positions from `min` to `max` onto the gradient.
+    pub fn new(leds: Vec<Led>, gradient: Gradient, min: u64, max: u64) -> Self {
+        FollowKnob {
+            leds,
+            gradient,
+            range: (min.min(max), min.max(max)),
+            position: None,
+        }
+    }
+
+    /// Use the value range of the active knob.
+    pub fn with_knob(leds: Vec<Led>, gradient: Gradient, knob: &Knob) -> Self {
+        Self::new(leds, gradient, knob.value_min as u64, knob.value_max as u64)
+    }
+}
+
+impl Layer for FollowKnob {
+    fn render(&mut self, _elapsed: Duration, frame: &mut LedFrame) {
+        let Some(position) = self.position else {
+            return;
+        };
+        let (min, max) = self.range;
+        let t = if max > min {
+            (position.clamp(min, max) - min) as f32 / (max - min) as f32
+        } else {
+            0.0
+        };
+        let color = self.gradient.at(t);
+        for led in &self.leds {
+            frame.set(*led, color);
+        }
+    }
+
+    fn handle(&mut self, message: &Message, _elapsed: Duration) {
+        if let Message::Event(Event::Position(position)) = message {
+            self.position = Some(*position);
+        }
+    }
+}
+
+/// Drives the LED colors of a profile from the host.
+///
+/// Every frame the layers are drawn on top of each other and the LEDs that
+/// changed are handed out as a [`Profile`] holding only those colors, at most
+/// `fps` times per second.
+///
+/// The firmware has no known command for writing a profile's colors, so
+/// getting the frames to the device is left to the caller.
+///
+/// ```no_run
+/// use bongoknob::{Animator, Breathing, Color, KeyPulse, Led};
+/// use std::time::Duration;
+///
+/// # let device: bongoknob::Device = unimplemented!();
+/// let mut animator = Animator::new(30)
+///     .layer(Breathing {
+///         leds: vec![Led::Primary, Led::Secondary],
+///         color: Color::new(0, 80, 255),
+///         period: Duration::from_secs(4),
+///     })
+///     .layer(KeyPulse::new(Color::new(255, 255, 255), Duration::from_millis(300)));
+/// animator.run(&device, |colors| {
+///     println!("{:?}", colors.primary);
+///     Ok(())
+/// })?;
+/// # Ok::<(), bongoknob::Error>(())
+/// ```
+pub struct Animator {
+    interval: Duration,
+    layers: Vec<Box<dyn Layer>>,
+    started: Instant,
+    last_frame: Option<Instant>,
+    sent: LedFrame,
+}
+
+impl fmt::Debug for Animator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Animator")
+            .field("interval", &self.interval)
+            .field("layers", &self.layers.len())
+            .finish()
+    }
+}
+
+impl Animator {
+    /// Animate the LEDs, producing at most `fps` frames per second.
+    pub fn new(fps: u32) -> Self {
+        Animator {
+            interval: Duration::from_secs(1) / fps.max(1),
+            layers: Vec::new(),
+            started: Instant::now(),
+            last_frame: None,
+            sent: LedFrame::default(),
+        }
+    }
+
+    pub fn layer(mut self, layer: impl Layer + 'static) -> Self {
+        self.push_layer(layer);
+        self
+    }
+
+    pub fn push_layer(&mut self, layer: impl Layer + 'static) {
+        self.layers.push(Box::new(layer));
+    }
+
+    pub fn clear_layers(&mut self) {
+        self.layers.clear();
+    }
+
+    /// Pass a message from the device on to the layers.
+    pub fn handle(&mut self, message: &Message) {
+        self.handle_at(message, Instant::now());
+    }
+
+    /// Pass a message received at `now` on to the layers.
+    pub fn handle_at(&mut self, message: &Message, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.started);
+        for layer in self.layers.iter_mut() {
+            layer.handle(message, elapsed);
+        }
+    }
+
+    /// Draw all layers at `now`, whether or not a frame is due.
+    pub fn render(&mut self, now: Instant) -> LedFrame {
+        let elapsed = now.saturating_duration_since(self.started);
+        let mut frame = LedFrame::default();
+        for layer in self.layers.iter_mut() {
+            layer.render(elapsed, &mut frame);
+        }
+        frame
+    }
+
+    /// The colors that changed since the last frame, if a frame is due at
+    /// `now` and anything changed.
+    pub fn poll(&mut self, now: Instant) -> Option<Profile> {
+        if let Some(last) = self.last_frame {
+            if now < last + self.interval {
+                return None;
+            }
+        }
+        self.last_frame = Some(now);
+
+        let frame = self.render(now);
+        let changes = frame.changes(&self.sent)?;
+        self.sent = frame;
+        Some(changes)
+    }
+
+    /// Animate until the device goes away, feeding its events to the layers
+    /// and passing every frame's changed colors to `apply`.
+    pub fn run<F>(&mut self, device: &impl KnobDevice, mut apply: F) -> Result<(), Error>
+    where
+        F: FnMut(Profile) -> Result<(), Error>,
+    {
+        let messages = device.subscribe();
+        loop {
+            let wait = match self.last_frame {
+                Some(last) => (last + self.interval).saturating_duration_since(Instant::now()),
+                None => Duration::ZERO,
+            };
+            match messages.recv_timeout(wait) {
+                Ok(message) => self.handle(&message),
+                Err(crossbeam::channel::RecvTimeoutError::Timeout) => {}
+                Err(crossbeam::channel::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+            if let Some(colors) = self.poll(Instant::now()) {
+                apply(colors)?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layers_and_frame_rate() {
+        let red = Color::new(255, 0, 0);
+        let mut animator = Animator::new(10)
+            .layer(FollowKnob::new(
+                vec![Led::Primary, Led::ButtonIdle(1)],
+                Gradient::new(&[Color::new(0, 0, 0), Color::new(0, 0, 200)]),
+                0,
+                100,
+            ))
+            .layer(KeyPulse::new(red, Duration::from_millis(100)));
+        let start = animator.started;
+
+        // nothing drawn yet
+        assert!(animator.poll(start).is_none());
+
+        animator.handle(&Message::Event(Event::Position(50)));
+        let profile = animator.poll(start + Duration::from_millis(100)).unwrap();
+        assert_eq!(profile.primary, Some(Color::new(0, 0, 100)));
+        assert_eq!(profile.button_b_idle, Some(Color::new(0, 0, 100)));
+        assert_eq!(profile.secondary, None);
+
+        // frame rate is bounded
+        animator.handle(&Message::Event(Event::Position(100)));
+        assert!(animator.poll(start + Duration::from_millis(150)).is_none());
+
+        // the key pulse is drawn over the knob color, only changes are sent
+        let pressed = start + Duration::from_millis(180);
+        animator.handle_at(
+            &Message::Event(Event::Key(KeyEvent::Down {
+                keys: [false, true, false, false],
+                id: 1,
+            })),
+            pressed,
+        );
+        let profile = animator.poll(pressed + Duration::from_millis(20)).unwrap();
+        assert_eq!(profile.primary, Some(Color::new(0, 0, 200)));
+        assert!(profile.button_b_idle.unwrap().r > 200);
+        assert_eq!(profile.secondary, None);
+
+        // once faded the button shows the knob color again, not black
+        let profile = animator.poll(pressed + Duration::from_millis(200)).unwrap();
+        assert_eq!(profile.button_b_idle, Some(Color::new(0, 0, 200)));
+    }
+
+    #[test]
+    fn test_rainbow_and_breathing() {
+        let mut frame = LedFrame::default();
+        let mut breathing = Breathing {
+            leds: vec![Led::Pointer],
+            color: Color::new(200, 100, 0),
+            period: Duration::from_secs(2),
+        };
+        breathing.render(Duration::ZERO, &mut frame);
+        assert_eq!(frame.get(Led::Pointer), Some(Color::new(0, 0, 0)));
+        breathing.render(Duration::from_secs(1), &mut frame);
+        assert_eq!(frame.get(Led::Pointer), Some(Color::new(200, 100, 0)));
+
+        let mut rainbow = Rainbow {
+            leds: vec![Led::ButtonIdle(0), Led::ButtonIdle(1), Led::ButtonIdle(2)],
+            period: Duration::from_secs(3),
+            saturation: 1.0,
+            value: 1.0,
+        };
+        rainbow.render(Duration::ZERO, &mut frame);
+        assert_eq!(frame.get(Led::ButtonIdle(0)), Some(Color::new(255, 0, 0)));
+        assert_eq!(frame.get(Led::ButtonIdle(1)), Some(Color::new(0, 255, 0)));
+        assert_eq!(frame.get(Led::ButtonIdle(2)), Some(Color::new(0, 0, 255)));
+        rainbow.render(Duration::from_secs(1), &mut frame);
+        assert_eq!(frame.get(Led::ButtonIdle(0)), Some(Color::new(0, 255, 0)));
+    }
+}
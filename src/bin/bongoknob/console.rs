@@ -0,0 +1,180 @@
+use anyhow::Result;
+use bongoknob::{Device, Message, RawLine};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, ExternalPrinter, Helper};
+use std::path::PathBuf;
+use std::thread;
+
+/// Top level keys the firmware understands.
+const KEYS: &[&str] = &[
+    "profiles",
+    "profile",
+    "current",
+    "settings",
+    "save",
+    "load",
+    "recalibrate",
+    "screen",
+];
+
+struct KeyCompleter;
+
+impl Completer for KeyCompleter {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        Ok(complete(line, pos))
+    }
+}
+
+impl Hinter for KeyCompleter {
+    type Hint = String;
+}
+
+impl Highlighter for KeyCompleter {}
+
+impl Validator for KeyCompleter {}
+
+impl Helper for KeyCompleter {}
+
+/// Complete the key under the cursor. A bare word at the start of the line
+/// expands to the opening of a JSON object.
+fn complete(line: &str, pos: usize) -> (usize, Vec<Pair>) {
+    let start = line[..pos]
+        .char_indices()
+        .rfind(|(_, c)| !c.is_ascii_alphanumeric() && *c != '_')
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let prefix = &line[start..pos];
+    let bare = line[..start].trim().is_empty();
+
+    let candidates = KEYS
+        .iter()
+        .filter(|key| key.starts_with(prefix))
+        .map(|key| Pair {
+            display: key.to_string(),
+            replacement: if bare {
+                format!("{{\"{}\": ", key)
+            } else {
+                key.to_string()
+            },
+        })
+        .collect();
+    (start, candidates)
+}
+
+/// Describe a line from the device: which message it parsed to, then the
+/// line itself, pretty printed if it is JSON.
+fn describe(line: &RawLine) -> String {
+    let marker = match &line.parsed {
+        Ok(message) => format!("<- {}", message.kind()),
+        Err(e) => format!("<- unparsed ({})", e),
+    };
+    let body = match serde_json::from_str::<serde_json::Value>(&line.line) {
+        Ok(value) => serde_json::to_string_pretty(&value).unwrap(),
+        Err(_) => line.line.clone(),
+    };
+    format!("{}\n{}", marker, body)
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".bongoknob_history"))
+}
+
+/// Read JSON lines from the user and send them verbatim, printing whatever
+/// the device sends back.
+pub fn run(device: &Device, show_heartbeats: bool) -> Result<()> {
+    let mut editor: Editor<KeyCompleter, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(KeyCompleter));
+    let history = history_path();
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+
+    let lines = device.subscribe_raw();
+    let mut printer = editor.create_external_printer()?;
+    thread::spawn(move || {
+        for line in lines.iter() {
+            if !show_heartbeats && matches!(line.parsed, Ok(Message::Heartbeat(_))) {
+                continue;
+            }
+            if printer.print(describe(&line)).is_err() {
+                break;
+            }
+        }
+    });
+
+    println!("Type JSON to send it to the device, tab completes keys, ctrl-d quits.");
+    loop {
+        match editor.readline("-> ") {
+            Ok(input) => {
+                let input = input.trim();
+                if input.is_empty() {
+                    continue;
+                }
+                editor.add_history_entry(input)?;
+                if serde_json::from_str::<serde_json::Value>(input).is_err() {
+                    println!("(not valid JSON, sending anyway)");
+                }
+                device.send_raw(input)?;
+            }
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    if let Some(path) = &history {
+        editor.save_history(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_complete() {
+        let (start, pairs) = complete("pro", 3);
+        assert_eq!(start, 0);
+        let replacements: Vec<&str> = pairs.iter().map(|p| p.replacement.as_str()).collect();
+        assert_eq!(replacements, vec!["{\"profiles\": ", "{\"profile\": "]);
+
+        let (start, pairs) = complete("{\"sa", 4);
+        assert_eq!(start, 2);
+        assert_eq!(pairs[0].replacement, "save");
+
+        let line = "{\"ösa";
+        let (start, pairs) = complete(line, line.len());
+        assert_eq!(&line[start..], "sa");
+        assert_eq!(pairs[0].replacement, "save");
+    }
+
+    #[test]
+    fn test_describe() {
+        let line = RawLine {
+            received_at: Instant::now(),
+            line: r#"{"idle":10}"#.to_string(),
+            parsed: Message::try_from(r#"{"idle":10}"#).map_err(|e| e.to_string()),
+        };
+        assert!(describe(&line).starts_with("<- Heartbeat\n{"));
+
+        let line = RawLine {
+            received_at: Instant::now(),
+            line: "boot".to_string(),
+            parsed: Err("expected value".to_string()),
+        };
+        assert_eq!(describe(&line), "<- unparsed (expected value)\nboot");
+    }
+}
@@ -0,0 +1,323 @@
+#[cfg(feature = "console")]
+mod console;
+mod output;
+#[cfg(feature = "tui")]
+mod tui;
+
+use anyhow::{anyhow, bail, Context, Result};
+use bongoknob::{Command, ConnectOptions, Daemon, Device, Message, Settings};
+use clap::{Parser, Subcommand};
+use output::Output;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+/// Manage bongoknob devices from the command line.
+#[derive(Parser, Debug)]
+#[command(name = "bongoknob", version)]
+struct Cli {
+    /// Serial port of the knob, defaults to the first one found
+    #[arg(short, long, global = true)]
+    port: Option<String>,
+
+    /// Print machine readable JSON instead of text
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Cmd,
+}
+
+#[derive(Subcommand, Debug)]
+enum Cmd {
+    /// List connected knobs
+    List,
+    /// Show the device settings
+    Info,
+    /// List the profiles on the device
+    Profiles,
+    /// Show or switch profiles
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCmd,
+    },
+    /// Change device settings
+    Settings {
+        #[command(subcommand)]
+        command: SettingsCmd,
+    },
+    /// Save settings and profiles to flash
+    Save,
+    /// Reload settings and profiles from flash
+    Load,
+    /// Reset the motor calibration
+    Recalibrate,
+    /// Show a message on the device screen
+    Message {
+        title: String,
+        /// Smaller text below the title
+        #[arg(long)]
+        text: Option<String>,
+        /// How long to show the message, in seconds
+        #[arg(long, default_value_t = 3.0)]
+        duration: f32,
+    },
+    /// Print everything the device sends until interrupted
+    Monitor,
+    /// Share the knob with other processes over a Unix socket
+    Daemon {
+        /// Socket to listen on
+        #[arg(long, value_name = "PATH")]
+        socket: Option<PathBuf>,
+    },
+    /// Send raw JSON lines and inspect every reply
+    #[cfg(feature = "console")]
+    Console {
+        /// Also print heartbeats
+        #[arg(long)]
+        heartbeats: bool,
+    },
+    /// Live dashboard of position, keys and messages
+    #[cfg(feature = "tui")]
+    Tui,
+}
+
+#[derive(Subcommand, Debug)]
+enum ProfileCmd {
+    /// Print a profile
+    Show { name: String },
+    /// Make a profile the active one
+    Use { name: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum SettingsCmd {
+    /// Set one or more settings, e.g. `led_max_brightness=120`
+    Set {
+        #[arg(required = true, value_name = "KEY=VALUE")]
+        values: Vec<String>,
+    },
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let out = Output::new(cli.json);
+    match cli.command {
+        // listing doesn't need a knob to be connected
+        Cmd::List => list(&out),
+        command => run(&out, cli.port.as_deref(), command),
+    }
+}
+
+fn run(out: &Output, port: Option<&str>, command: Cmd) -> Result<()> {
+    let (device, port) = open(port)?;
+    match command {
+        Cmd::List => list(out)?,
+        Cmd::Info => out.value(&device.get_settings()?),
+        Cmd::Profiles => {
+            let current = device.get_current_profile()?;
+            let profiles = device.get_profiles()?;
+            if out.json {
+                out.print(&json!({ "current": current, "profiles": profiles }));
+            } else {
+                for profile in profiles {
+                    let marker = if profile == current { '*' } else { ' ' };
+                    println!("{} {}", marker, profile);
+                }
+            }
+        }
+        Cmd::Profile { command } => match command {
+            ProfileCmd::Show { name } => out.value(&device.get_profile(&name)?),
+            ProfileCmd::Use { name } => {
+                if !device.get_profiles()?.contains(&name) {
+                    bail!("no profile named `{}`", name);
+                }
+                device.set_profile(&name)?;
+                confirm(&device, out, &format!("switched to {}", name))?;
+            }
+        },
+        Cmd::Settings {
+            command: SettingsCmd::Set { values },
+        } => {
+            device.set_settings(parse_settings(&values)?)?;
+            confirm(&device, out, "settings updated")?;
+        }
+        Cmd::Save => match device.command_response(Command::Save)? {
+            Message::Saved(_) => out.status("saved"),
+            message => bail!("unexpected response: {}", message),
+        },
+        Cmd::Load => {
+            device.command_response(Command::Load)?;
+            out.status("loaded");
+        }
+        Cmd::Recalibrate => {
+            device.recalibrate()?;
+            confirm(&device, out, "recalibrating")?;
+        }
+        Cmd::Message {
+            title,
+            text,
+            duration,
+        } => {
+            device.set_message(Some(title), text, Some(duration))?;
+            confirm(&device, out, "message sent")?;
+        }
+        Cmd::Monitor => {
+            for envelope in device.subscribe_envelopes().iter() {
+                if out.json {
+                    println!("{}", envelope.raw);
+                } else {
+                    println!("{}", envelope.message);
+                }
+            }
+        }
+        Cmd::Daemon { socket } => {
+            let socket = socket.unwrap_or_else(bongoknob::default_socket_path);
+            let daemon = Daemon::bind(device, &socket)?;
+            out.status(&format!("sharing {} on {}", port, socket.display()));
+            daemon.run()?;
+        }
+        #[cfg(feature = "console")]
+        Cmd::Console { heartbeats } => console::run(&device, heartbeats)?,
+        #[cfg(feature = "tui")]
+        Cmd::Tui => tui::run(&device, &port)?,
+    }
+
+    Ok(())
+}
+
+fn list(out: &Output) -> Result<()> {
+    let devices = match bongoknob::discover() {
+        Ok(devices) => devices,
+        Err(bongoknob::Error::NoDevicesFound) => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+    if out.json {
+        let devices: Vec<Value> = devices
+            .iter()
+            .map(|d| json!({ "port": d.port_name(), "product": d.product() }))
+            .collect();
+        out.print(&json!(devices));
+    } else if devices.is_empty() {
+        println!("no devices found");
+    } else {
+        for device in devices {
+            println!(
+                "{}\t{}",
+                device.port_name(),
+                device.product().unwrap_or("Unknown")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Connect to the knob, returning it with the path it was opened at.
+fn open(port: Option<&str>) -> Result<(Device, String)> {
+    let options = match port {
+        Some(port) => ConnectOptions::new(port),
+        None => {
+            let device = bongoknob::discover()?.remove(0);
+            ConnectOptions::from(device)
+        }
+    };
+    let path = options.path().to_string();
+    let device = options
+        .connect()
+        .with_context(|| format!("could not open {}", path))?;
+    Ok((device, path))
+}
+
+/// Wait for the device to answer, so commands without a response are
+/// written before the process exits.
+fn confirm(device: &Device, out: &Output, message: &str) -> Result<()> {
+    device.get_current_profile()?;
+    out.status(message);
+    Ok(())
+}
+
+/// Build settings from `key=value` pairs. Keys may be snake or camel case,
+/// values are parsed as JSON and fall back to plain strings.
+fn parse_settings(pairs: &[String]) -> Result<Settings> {
+    let mut settings = serde_json::Map::new();
+    for pair in pairs {
+        let (key, value) = pair
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected KEY=VALUE, got `{}`", pair))?;
+        let value = serde_json::from_str(value).unwrap_or_else(|_| json!(value));
+        settings.insert(camel_case(key.trim()), value);
+    }
+
+    // unknown keys are silently dropped by serde, catch them here
+    let known = setting_keys()?;
+    if let Some(key) = settings.keys().find(|key| !known.contains_key(*key)) {
+        bail!("unknown setting `{}`", key);
+    }
+    serde_json::from_value(Value::Object(settings)).context("invalid setting value")
+}
+
+/// Every settings key, taken from settings with all of them filled in so a
+/// new field can't be forgotten.
+fn setting_keys() -> Result<serde_json::Map<String, Value>> {
+    let settings = Settings {
+        debug: Some(false),
+        led_max_brightness: Some(0),
+        max_velocity: Some(0),
+        max_voltage: Some(0),
+        device_orientation: Some(0),
+        device_name: Some(String::new()),
+        wifi_enabled: Some(false),
+        serial_number: Some(String::new()),
+        firmware_version: Some(String::new()),
+        midi_usb: Some(Default::default()),
+        midi2: Some(Default::default()),
+        sysex_id: Some(0),
+        idle_timeout: Some(0),
+    };
+    match serde_json::to_value(settings)? {
+        Value::Object(keys) => Ok(keys),
+        _ => unreachable!("settings serialize to an object"),
+    }
+}
+
+fn camel_case(key: &str) -> String {
+    let mut out = String::with_capacity(key.len());
+    let mut upper = false;
+    for c in key.chars() {
+        if c == '_' || c == '-' {
+            upper = true;
+        } else if upper {
+            out.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_settings() {
+        let pairs = [
+            "led_max_brightness=120".to_string(),
+            "deviceName=left knob".to_string(),
+            "midi-usb={\"in\":true,\"out\":false,\"thru\":false,\"route\":false,\"nano\":true}"
+                .to_string(),
+        ];
+        let settings = parse_settings(&pairs).unwrap();
+        assert_eq!(settings.led_max_brightness, Some(120));
+        assert_eq!(settings.device_name.as_deref(), Some("left knob"));
+        assert!(settings.midi_usb.unwrap().input);
+
+        let settings = parse_settings(&["device_name=null".to_string()]).unwrap();
+        assert_eq!(settings.device_name, None);
+
+        assert!(parse_settings(&["nope=1".to_string()]).is_err());
+        assert!(parse_settings(&["nope=null".to_string()]).is_err());
+        assert!(parse_settings(&["led_max_brightness=bright".to_string()]).is_err());
+        assert!(parse_settings(&["debug".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,71 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Prints results either for people or as JSON.
+#[derive(Debug, Clone, Copy)]
+pub struct Output {
+    pub json: bool,
+}
+
+impl Output {
+    pub fn new(json: bool) -> Self {
+        Output { json }
+    }
+
+    pub fn print(&self, value: &Value) {
+        println!("{}", serde_json::to_string_pretty(value).unwrap());
+    }
+
+    /// A struct as JSON, or as `key: value` lines.
+    pub fn value(&self, value: &impl Serialize) {
+        let value = serde_json::to_value(value).unwrap();
+        if self.json {
+            self.print(&value);
+        } else {
+            print_fields(&value, 0);
+        }
+    }
+
+    /// Outcome of a command that has nothing else to show.
+    pub fn status(&self, message: &str) {
+        if self.json {
+            println!("{}", json!({ "ok": true, "status": message }));
+        } else {
+            println!("{}", message);
+        }
+    }
+}
+
+fn print_fields(value: &Value, indent: usize) {
+    let pad = "  ".repeat(indent);
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                if value.is_object() || value.is_array() {
+                    println!("{}{}:", pad, key);
+                    print_fields(value, indent + 1);
+                } else {
+                    println!("{}{}: {}", pad, key, scalar(value));
+                }
+            }
+        }
+        Value::Array(items) if items.iter().all(|v| !v.is_object()) => {
+            let items: Vec<String> = items.iter().map(scalar).collect();
+            println!("{}{}", pad, items.join(", "));
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                println!("{}[{}]", pad, i);
+                print_fields(item, indent + 1);
+            }
+        }
+        _ => println!("{}{}", pad, scalar(value)),
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
@@ -0,0 +1,259 @@
+use anyhow::Result;
+use bongoknob::{Device, Event, KeyEvent, Knob, Message, PositionTracker, RawLine};
+use ratatui::crossterm::event::{self, Event as TermEvent, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Gauge, List, ListItem, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const LOG_LINES: usize = 500;
+
+/// What the dashboard shows, updated from the lines the device sends.
+struct Dashboard {
+    port: String,
+    profile: String,
+    range: (u64, u64),
+    tracker: PositionTracker,
+    last_key: Option<KeyEvent>,
+    started: Instant,
+    log: VecDeque<(Duration, String, bool)>,
+    show_heartbeats: bool,
+}
+
+impl Dashboard {
+    fn new(port: &str, profile: &str, knob: Option<&Knob>) -> Self {
+        let (tracker, range) = match knob {
+            Some(knob) => (
+                PositionTracker::with_knob(knob),
+                (knob.value_min as u64, knob.value_max as u64),
+            ),
+            None => (PositionTracker::new(), (0, 255)),
+        };
+        Dashboard {
+            port: port.to_string(),
+            profile: profile.to_string(),
+            range,
+            tracker,
+            last_key: None,
+            started: Instant::now(),
+            log: VecDeque::new(),
+            show_heartbeats: false,
+        }
+    }
+
+    fn handle(&mut self, line: &RawLine) {
+        let (text, error) = match &line.parsed {
+            Ok(message) => {
+                match message {
+                    Message::Event(Event::Position(position)) => {
+                        self.tracker.update(*position, line.received_at);
+                    }
+                    Message::Event(Event::Key(key)) => self.last_key = Some(key.clone()),
+                    Message::Heartbeat(_) if !self.show_heartbeats => return,
+                    _ => {}
+                }
+                (message.to_string(), matches!(message, Message::Error(_)))
+            }
+            Err(e) => (format!("{} ({})", line.line, e), true),
+        };
+
+        let at = line.received_at.saturating_duration_since(self.started);
+        self.log.push_back((at, text, error));
+        if self.log.len() > LOG_LINES {
+            self.log.pop_front();
+        }
+    }
+
+    /// Share of the knob range the position is at, from 0.0 to 1.0.
+    fn ratio(&self) -> f64 {
+        let (min, max) = self.range;
+        match self.tracker.position() {
+            Some(position) if max > min => {
+                (position.clamp(min, max) - min) as f64 / (max - min) as f64
+            }
+            _ => 0.0,
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame, device: &Device) {
+        let [header, knob, keys, log] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Min(3),
+        ])
+        .areas(frame.area());
+
+        let idle = match device.idle_duration() {
+            Some(idle) => format!("{:.1}s", idle.as_secs_f32()),
+            None => "-".to_string(),
+        };
+        let heartbeat = match device.last_heartbeat() {
+            Some(at) => format!("{:.1}s ago", at.elapsed().as_secs_f32()),
+            None => "never".to_string(),
+        };
+        let status = format!(
+            "Profile: {}   Idle: {}   Last heartbeat: {}   Dropped: {}",
+            self.profile,
+            idle,
+            heartbeat,
+            device.dropped_messages()
+        );
+        frame.render_widget(
+            Paragraph::new(status).block(block(&format!("bongoknob {}", self.port))),
+            header,
+        );
+
+        let label = match self.tracker.position() {
+            Some(position) => format!(
+                "{} ({}..{})  {:+.1}/s",
+                position,
+                self.range.0,
+                self.range.1,
+                self.tracker.velocity()
+            ),
+            None => "no position yet".to_string(),
+        };
+        frame.render_widget(
+            Gauge::default()
+                .block(block("Knob"))
+                .gauge_style(Style::default().fg(Color::Cyan))
+                .ratio(self.ratio())
+                .label(label),
+            knob,
+        );
+
+        let (state, pressed) = match &self.last_key {
+            Some(key) => (key.pretty_print(), key.keys()),
+            None => ("[OOOO]".to_string(), [false; 4]),
+        };
+        let mut spans = vec![Span::raw(format!("{}  ", state))];
+        for (i, pressed) in pressed.iter().enumerate() {
+            let style = if *pressed {
+                Style::default().fg(Color::Black).bg(Color::Yellow)
+            } else {
+                Style::default().fg(Color::DarkGray)
+            };
+            spans.push(Span::styled(
+                format!(" {} ", (b'A' + i as u8) as char),
+                style,
+            ));
+            spans.push(Span::raw(" "));
+        }
+        frame.render_widget(Paragraph::new(Line::from(spans)).block(block("Keys")), keys);
+
+        self.draw_log(frame, log);
+    }
+
+    fn draw_log(&self, frame: &mut Frame, area: Rect) {
+        let visible = area.height.saturating_sub(2) as usize;
+        let items: Vec<ListItem> = self
+            .log
+            .iter()
+            .skip(self.log.len().saturating_sub(visible))
+            .map(|(at, text, error)| {
+                let style = if *error {
+                    Style::default().fg(Color::Red)
+                } else {
+                    Style::default()
+                };
+                ListItem::new(format!("{:>8.2} {}", at.as_secs_f32(), text)).style(style)
+            })
+            .collect();
+        let title = if self.show_heartbeats {
+            "Log (q quit, c clear, h hide heartbeats)"
+        } else {
+            "Log (q quit, c clear, h show heartbeats)"
+        };
+        frame.render_widget(List::new(items).block(block(title)), area);
+    }
+}
+
+fn block(title: &str) -> Block<'_> {
+    Block::default().borders(Borders::ALL).title(Span::styled(
+        title,
+        Style::default().add_modifier(Modifier::BOLD),
+    ))
+}
+
+/// Show a live dashboard of the device until the user quits.
+pub fn run(device: &Device, port: &str) -> Result<()> {
+    let profile = device.get_current_profile()?;
+    let knob = device
+        .get_profile(&profile)
+        .ok()
+        .and_then(|p| p.knob)
+        .and_then(|k| k.into_iter().next());
+    let mut dashboard = Dashboard::new(port, &profile, knob.as_ref());
+    let lines = device.subscribe_raw();
+
+    let mut terminal = ratatui::init();
+    let result = run_loop(&mut terminal, &mut dashboard, device, &lines);
+    ratatui::restore();
+    result
+}
+
+fn run_loop(
+    terminal: &mut DefaultTerminal,
+    dashboard: &mut Dashboard,
+    device: &Device,
+    lines: &bongoknob::Subscription<RawLine>,
+) -> Result<()> {
+    loop {
+        for line in lines.try_iter() {
+            dashboard.handle(&line);
+        }
+        terminal.draw(|frame| dashboard.draw(frame, device))?;
+
+        if event::poll(Duration::from_millis(50))? {
+            if let TermEvent::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    KeyCode::Char('c') => dashboard.log.clear(),
+                    KeyCode::Char('h') => dashboard.show_heartbeats = !dashboard.show_heartbeats,
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(raw: &str) -> RawLine {
+        RawLine {
+            received_at: Instant::now(),
+            line: raw.to_string(),
+            parsed: Message::try_from(raw).map_err(|e| e.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_dashboard() {
+        let mut dashboard = Dashboard::new("/dev/ttyACM0", "test", None);
+        dashboard.range = (0, 100);
+
+        dashboard.handle(&line(r#"{"p":25}"#));
+        dashboard.handle(&line(r#"{"kd":1,"ks":2}"#));
+        dashboard.handle(&line(r#"{"idle":100}"#));
+        dashboard.handle(&line("garbage"));
+
+        assert_eq!(dashboard.ratio(), 0.25);
+        assert_eq!(
+            dashboard.last_key.as_ref().unwrap().pretty_print(),
+            "[OXOO]"
+        );
+        // heartbeats are hidden by default, parse failures are errors
+        assert_eq!(dashboard.log.len(), 3);
+        assert!(dashboard.log[2].2);
+        assert!(!dashboard.log[0].2);
+    }
+}
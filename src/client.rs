@@ -0,0 +1,152 @@
+use crate::error::Error;
+use crate::knob_device::KnobDevice;
+use crate::responses::{Reply, ResponseQueue};
+use crate::subscription::{Bus, SubscriptionBuilder, SubscriptionOptions};
+use crate::{Command, Message};
+use crossbeam::channel::bounded;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+/// A knob shared by a [`Daemon`](crate::Daemon), used like a [`Device`](crate::Device)
+/// through [`KnobDevice`].
+///
+/// ```no_run
+/// use bongoknob::{Client, KnobDevice};
+///
+/// let knob = Client::connect(bongoknob::default_socket_path())?;
+/// println!("{:?}", knob.get_settings()?);
+/// for position in knob.subscribe_positions().iter() {
+///     println!("{}", position);
+/// }
+/// # Ok::<(), bongoknob::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct Client {
+    stream: Mutex<UnixStream>,
+    pending: Arc<Mutex<ResponseQueue>>,
+    bus: Arc<Bus>,
+    subscription_options: SubscriptionOptions,
+}
+
+impl Client {
+    pub fn connect(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::connect_with(path, SubscriptionOptions::default())
+    }
+
+    /// Like [`connect`](Client::connect), with the default queue settings
+    /// for new subscriptions.
+    pub fn connect_with(
+        path: impl AsRef<Path>,
+        subscription_options: SubscriptionOptions,
+    ) -> Result<Self, Error> {
+        let stream = UnixStream::connect(path)?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let pending: Arc<Mutex<ResponseQueue>> = Arc::default();
+        let bus = Arc::new(Bus::default());
+
+        let responders = pending.clone();
+        let message_pipe = bus.clone();
+        thread::spawn(move || {
+            for line in reader.lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if let Some(envelope) = message_pipe.publish_line(line, Instant::now()) {
+                    responders.lock().unwrap().route(envelope);
+                }
+            }
+            // fail whatever is still waiting
+            *responders.lock().unwrap() = ResponseQueue::default();
+        });
+
+        Ok(Client {
+            stream: Mutex::new(stream),
+            pending,
+            bus,
+            subscription_options,
+        })
+    }
+
+    fn send(&self, command: &Command, reply: Reply) -> Result<(), Error> {
+        let mut stream = self.stream.lock().unwrap();
+        // queue before writing so a quick reply can't arrive first
+        let id = self.pending.lock().unwrap().push(reply, Instant::now());
+        writeln!(stream, "{}", command.to_string()).map_err(|_| {
+            self.pending.lock().unwrap().cancel(id);
+            Error::CommandSendError
+        })
+    }
+
+    /// Messages dropped across all subscriptions since connecting.
+    pub fn dropped_messages(&self) -> u64 {
+        self.bus.dropped()
+    }
+}
+
+impl KnobDevice for Client {
+    fn command_response(&self, command: Command) -> Result<Message, Error> {
+        let (tx, rx) = bounded(1);
+        self.send(&command, Reply::response(tx))?;
+        match rx.recv().map_err(|_| Error::CommandSendError)?.message {
+            Message::Error(e) => Err(Error::CommandError(e.error, e.msg)),
+            message => Ok(message),
+        }
+    }
+
+    fn command(&self, command: Command) -> Result<(), Error> {
+        self.send(&command, Reply::ignore(&command))
+    }
+
+    fn subscription(&self) -> SubscriptionBuilder<'_> {
+        SubscriptionBuilder::new(&self.bus, self.subscription_options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::time::Duration;
+
+    #[test]
+    fn test_client() {
+        let path =
+            std::env::temp_dir().join(format!("bongoknob-client-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        // fake daemon: an event before each answer, errors for unknown profiles
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            for line in BufReader::new(stream).lines() {
+                let line = line.unwrap();
+                writeln!(writer, r#"{{"p":3}}"#).unwrap();
+                if line.contains("#all") {
+                    writeln!(writer, r#"{{"profiles":["A","B"],"current":"B"}}"#).unwrap();
+                } else if line.contains(r#""save""#) {
+                    writeln!(writer, r#"{{"saved":true}}"#).unwrap();
+                } else if line.contains(r#""profile""#) {
+                    writeln!(writer, r#"{{"error":"not found","msg":null}}"#).unwrap();
+                }
+            }
+        });
+
+        let client = Client::connect(&path).unwrap();
+        let positions = client.subscribe_positions();
+        assert_eq!(client.get_profiles().unwrap(), vec!["A", "B"]);
+        assert_eq!(client.get_current_profile().unwrap(), "B");
+        assert!(matches!(
+            client.get_profile("C"),
+            Err(Error::CommandError(e, None)) if e == "not found"
+        ));
+        client.save_settings().unwrap();
+        assert_eq!(positions.recv_timeout(Duration::from_secs(1)), Ok(3));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,522 @@
+use crate::error::Error;
+use crate::{Color, Profile};
+use std::fmt;
+use std::str::FromStr;
+
+impl Color {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Color { r, g, b }
+    }
+
+    /// Build a color from a packed `0xRRGGBB` value, as used by the firmware.
+    pub const fn from_u32(value: u32) -> Self {
+        Color {
+            r: (value >> 16) as u8,
+            g: (value >> 8) as u8,
+            b: value as u8,
+        }
+    }
+
+    pub const fn to_u32(&self) -> u32 {
+        (self.r as u32) << 16 | (self.g as u32) << 8 | self.b as u32
+    }
+
+    /// Hue in degrees, saturation and value from 0.0 to 1.0.
+    pub fn from_hsv(h: f32, s: f32, v: f32) -> Self {
+        let s = s.clamp(0.0, 1.0);
+        let v = v.clamp(0.0, 1.0);
+        let c = v * s;
+        Self::from_hue_chroma(h, c, v - c)
+    }
+
+    pub fn to_hsv(&self) -> (f32, f32, f32) {
+        let (h, max, min) = self.hue_max_min();
+        let s = if max == 0.0 { 0.0 } else { (max - min) / max };
+        (h, s, max)
+    }
+
+    /// Hue in degrees, saturation and lightness from 0.0 to 1.0.
+    pub fn from_hsl(h: f32, s: f32, l: f32) -> Self {
+        let s = s.clamp(0.0, 1.0);
+        let l = l.clamp(0.0, 1.0);
+        let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+        Self::from_hue_chroma(h, c, l - c / 2.0)
+    }
+
+    pub fn to_hsl(&self) -> (f32, f32, f32) {
+        let (h, max, min) = self.hue_max_min();
+        let l = (max + min) / 2.0;
+        let s = if max == min {
+            0.0
+        } else {
+            (max - min) / (1.0 - (2.0 * l - 1.0).abs())
+        };
+        (h, s, l)
+    }
+
+    /// Multiply every channel by `factor`, clamped to 0.0..=1.0.
+    pub fn scale(&self, factor: f32) -> Self {
+        let factor = factor.clamp(0.0, 1.0);
+        let scale = |c: u8| (c as f32 * factor).round() as u8;
+        Color::new(scale(self.r), scale(self.g), scale(self.b))
+    }
+
+    /// The color as it appears with `Settings::led_max_brightness` applied,
+    /// where 255 is full brightness.
+    pub fn with_max_brightness(&self, led_max_brightness: u8) -> Self {
+        self.scale(led_max_brightness as f32 / 255.0)
+    }
+
+    /// Linear interpolation towards `other`, `t` from 0.0 to 1.0.
+    pub fn lerp(&self, other: &Color, t: f32) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+        Color::new(
+            mix(self.r, other.r),
+            mix(self.g, other.g),
+            mix(self.b, other.b),
+        )
+    }
+
+    fn hue_max_min(&self) -> (f32, f32, f32) {
+        let (r, g, b) = (
+            self.r as f32 / 255.0,
+            self.g as f32 / 255.0,
+            self.b as f32 / 255.0,
+        );
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let delta = max - min;
+
+        let h = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        (h, max, min)
+    }
+
+    fn from_hue_chroma(h: f32, c: f32, m: f32) -> Self {
+        let h = h.rem_euclid(360.0) / 60.0;
+        let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+        let (r, g, b) = match h as u32 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        let channel = |v: f32| ((v + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+        Color::new(channel(r), channel(g), channel(b))
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+/// Parses `#rrggbb`, `#rgb` (the `#` is optional) and CSS color names.
+impl FromStr for Color {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let name = s.to_ascii_lowercase();
+        if let Some((_, value)) = CSS_COLORS.iter().find(|(n, _)| *n == name) {
+            return Ok(Color::from_u32(*value));
+        }
+
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        let invalid = || Error::ConversionError(format!("invalid color `{}`", s));
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        match hex.len() {
+            6 => Ok(Color::from_u32(
+                u32::from_str_radix(hex, 16).map_err(|_| invalid())?,
+            )),
+            3 => {
+                let v = u32::from_str_radix(hex, 16).map_err(|_| invalid())?;
+                let expand = |n: u32| ((n & 0xF) * 0x11) as u8;
+                Ok(Color::new(expand(v >> 8), expand(v >> 4), expand(v)))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Colors spaced along a line, sampled with [`at`](Gradient::at).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    stops: Vec<(f32, Color)>,
+}
+
+impl Gradient {
+    /// Spread `colors` evenly from 0.0 to 1.0.
+    pub fn new(colors: &[Color]) -> Self {
+        let last = colors.len().saturating_sub(1).max(1) as f32;
+        Gradient {
+            stops: colors
+                .iter()
+                .enumerate()
+                .map(|(i, c)| (i as f32 / last, *c))
+                .collect(),
+        }
+    }
+
+    /// Place colors at explicit positions between 0.0 and 1.0.
+    pub fn with_stops(stops: &[(f32, Color)]) -> Self {
+        let mut stops = stops.to_vec();
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Gradient { stops }
+    }
+
+    /// Color at `t`, clamped to the first and last stop.
+    pub fn at(&self, t: f32) -> Color {
+        let Some(first) = self.stops.first() else {
+            return Color::new(0, 0, 0);
+        };
+        if t <= first.0 {
+            return first.1;
+        }
+        for pair in self.stops.windows(2) {
+            let ((a_pos, a), (b_pos, b)) = (pair[0], pair[1]);
+            if t <= b_pos {
+                let span = b_pos - a_pos;
+                let local = if span > 0.0 { (t - a_pos) / span } else { 1.0 };
+                return a.lerp(&b, local);
+            }
+        }
+        self.stops.last().unwrap().1
+    }
+}
+
+/// A set of colors for every LED field of a [`Profile`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub primary: Color,
+    pub secondary: Color,
+    pub pointer: Color,
+    /// Idle colors of buttons A to D
+    pub button_idle: [Color; 4],
+    /// Pressed colors of buttons A to D
+    pub button_press: [Color; 4],
+}
+
+impl Palette {
+    /// Every button shares the same idle and pressed color.
+    pub fn new(
+        primary: Color,
+        secondary: Color,
+        pointer: Color,
+        idle: Color,
+        press: Color,
+    ) -> Self {
+        Palette {
+            primary,
+            secondary,
+            pointer,
+            button_idle: [idle; 4],
+            button_press: [press; 4],
+        }
+    }
+
+    /// Names accepted by [`named`](Palette::named).
+    pub const NAMES: &'static [&'static str] = &["ocean", "sunset", "forest", "neon", "mono"];
+
+    pub fn named(name: &str) -> Option<Self> {
+        let c = Color::from_u32;
+        let palette = match name.to_ascii_lowercase().as_str() {
+            "ocean" => Palette::new(
+                c(0x0077be),
+                c(0x00c2c7),
+                c(0xffffff),
+                c(0x003b5c),
+                c(0x00e5ff),
+            ),
+            "sunset" => Palette::new(
+                c(0xff5e3a),
+                c(0xffb347),
+                c(0xfff1c1),
+                c(0x5c1a33),
+                c(0xff9a00),
+            ),
+            "forest" => Palette::new(
+                c(0x228b22),
+                c(0x8fbc8f),
+                c(0xf5f5dc),
+                c(0x0b3d0b),
+                c(0x7cfc00),
+            ),
+            "neon" => Palette::new(
+                c(0xff00ff),
+                c(0x00ffff),
+                c(0xffff00),
+                c(0x2b0040),
+                c(0x39ff14),
+            ),
+            "mono" => Palette::new(
+                c(0xffffff),
+                c(0x808080),
+                c(0xffffff),
+                c(0x202020),
+                c(0xffffff),
+            ),
+            _ => return None,
+        };
+        Some(palette)
+    }
+
+    /// Palette whose colors are all scaled for `Settings::led_max_brightness`.
+    pub fn with_max_brightness(&self, led_max_brightness: u8) -> Self {
+        let scale = |c: &Color| c.with_max_brightness(led_max_brightness);
+        Palette {
+            primary: scale(&self.primary),
+            secondary: scale(&self.secondary),
+            pointer: scale(&self.pointer),
+            button_idle: self.button_idle.map(|c| scale(&c)),
+            button_press: self.button_press.map(|c| scale(&c)),
+        }
+    }
+
+    /// Set every LED color of `profile` from this palette.
+    pub fn apply(&self, profile: &mut Profile) {
+        profile.primary = Some(self.primary);
+        profile.secondary = Some(self.secondary);
+        profile.pointer = Some(self.pointer);
+        let [a, b, c, d] = self.button_idle;
+        profile.button_a_idle = Some(a);
+        profile.button_b_idle = Some(b);
+        profile.button_c_idle = Some(c);
+        profile.button_d_idle = Some(d);
+        let [a, b, c, d] = self.button_press;
+        profile.button_a_press = Some(a);
+        profile.button_b_press = Some(b);
+        profile.button_c_press = Some(c);
+        profile.button_d_press = Some(d);
+    }
+}
+
+impl FromStr for Palette {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Palette::named(s.trim())
+            .ok_or_else(|| Error::ConversionError(format!("unknown palette `{}`", s)))
+    }
+}
+
+const CSS_COLORS: &[(&str, u32)] = &[
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("#ff8000".parse::<Color>().unwrap(), Color::new(255, 128, 0));
+        assert_eq!("00FF7f".parse::<Color>().unwrap(), Color::new(0, 255, 127));
+        assert_eq!("#f80".parse::<Color>().unwrap(), Color::new(255, 136, 0));
+        assert_eq!(
+            "RebeccaPurple".parse::<Color>().unwrap(),
+            Color::new(102, 51, 153)
+        );
+        assert!("#ff80".parse::<Color>().is_err());
+        assert!("notacolor".parse::<Color>().is_err());
+        assert_eq!(Color::new(255, 128, 0).to_string(), "#ff8000");
+    }
+
+    #[test]
+    fn test_hsv_hsl() {
+        assert_eq!(Color::from_hsv(0.0, 1.0, 1.0), Color::new(255, 0, 0));
+        assert_eq!(Color::from_hsv(120.0, 1.0, 1.0), Color::new(0, 255, 0));
+        assert_eq!(Color::from_hsv(240.0, 1.0, 0.5), Color::new(0, 0, 128));
+        assert_eq!(Color::from_hsl(60.0, 1.0, 0.5), Color::new(255, 255, 0));
+        assert_eq!(Color::from_hsl(0.0, 0.0, 1.0), Color::new(255, 255, 255));
+
+        for color in [
+            Color::new(12, 200, 99),
+            Color::new(255, 128, 0),
+            Color::new(40, 40, 40),
+        ] {
+            let (h, s, v) = color.to_hsv();
+            assert_eq!(Color::from_hsv(h, s, v), color);
+            let (h, s, l) = color.to_hsl();
+            assert_eq!(Color::from_hsl(h, s, l), color);
+        }
+    }
+
+    #[test]
+    fn test_brightness_and_gradient() {
+        let orange = Color::new(255, 128, 0);
+        assert_eq!(orange.with_max_brightness(255), orange);
+        assert_eq!(orange.with_max_brightness(0), Color::new(0, 0, 0));
+        assert_eq!(orange.scale(0.5), Color::new(128, 64, 0));
+
+        let gradient = Gradient::new(&[
+            Color::new(0, 0, 0),
+            Color::new(200, 0, 0),
+            Color::new(200, 200, 0),
+        ]);
+        assert_eq!(gradient.at(-1.0), Color::new(0, 0, 0));
+        assert_eq!(gradient.at(0.25), Color::new(100, 0, 0));
+        assert_eq!(gradient.at(0.75), Color::new(200, 100, 0));
+        assert_eq!(gradient.at(2.0), Color::new(200, 200, 0));
+    }
+}
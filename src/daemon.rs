@@ -0,0 +1,221 @@
+use crate::error::Error;
+use crate::responses::Reply;
+use crate::{Command, Device, Message};
+use crossbeam::channel::unbounded;
+use crossbeam::select;
+use log::{error, info};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Where the daemon listens unless told otherwise: `$XDG_RUNTIME_DIR/bongoknob.sock`,
+/// falling back to `/tmp/bongoknob.sock`.
+pub fn default_socket_path() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/tmp"))
+        .join("bongoknob.sock")
+}
+
+/// Shares one [`Device`] among any number of local processes over a Unix
+/// domain socket.
+///
+/// Clients speak the device's own JSON lines. Events and heartbeats are sent
+/// to every client. Lines from clients are passed on to the device, and the
+/// lines it answers with, including errors for commands that are otherwise
+/// not answered, go back only to the client that sent them.
+///
+/// ```no_run
+/// let device = bongoknob::connect(bongoknob::discover()?.remove(0))?;
+/// let daemon = bongoknob::Daemon::bind(device, bongoknob::default_socket_path())?;
+/// daemon.run()?;
+/// # Ok::<(), bongoknob::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct Daemon {
+    device: Arc<Device>,
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl Daemon {
+    /// Listen on `path`, replacing a socket left behind by a daemon that is
+    /// no longer running.
+    pub fn bind(device: Device, path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        if path.exists() && UnixStream::connect(&path).is_err() {
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        Ok(Daemon {
+            device: Arc::new(device),
+            listener,
+            path,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Serve clients until the socket fails.
+    pub fn run(self) -> Result<(), Error> {
+        info!("listening on {}", self.path.display());
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            let device = self.device.clone();
+            thread::spawn(move || {
+                if let Err(e) = serve(&device, stream) {
+                    error!("client error: {}", e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Serve clients on a background thread.
+    pub fn spawn(self) -> JoinHandle<Result<(), Error>> {
+        thread::spawn(move || self.run())
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn serve(device: &Device, stream: UnixStream) -> Result<(), Error> {
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+
+    // forward events and this client's replies until it goes away
+    let events = device.subscribe_envelopes();
+    let (replies_tx, replies) = unbounded();
+    let event_writer = writer.clone();
+    thread::spawn(move || loop {
+        let envelope = select! {
+            recv(events) -> envelope => match envelope {
+                Ok(envelope) => match envelope.message {
+                    Message::Event(_) | Message::Heartbeat(_) => envelope,
+                    _ => continue,
+                },
+                Err(_) => break,
+            },
+            recv(replies) -> envelope => match envelope {
+                Ok(envelope) => envelope,
+                Err(_) => break,
+            },
+        };
+        if writeln!(event_writer.lock().unwrap(), "{}", envelope.raw).is_err() {
+            break;
+        }
+    });
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let command = Command::Raw(line.to_string());
+        let reply = Reply {
+            answered: command.expects_response(),
+            to: Some(replies_tx.clone()),
+        };
+        device.send_command(command, reply)?;
+    }
+
+    // unblock the event thread's next write
+    let _ = writer.lock().unwrap().shutdown(std::net::Shutdown::Both);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use serialport::{SerialPort, TTYPort};
+    use std::io::Read;
+    use std::time::Duration;
+
+    #[test]
+    fn test_expects_response() {
+        let expects_response = |line: &str| Command::Raw(line.to_string()).expects_response();
+        assert!(expects_response(r##"{"profiles":"#all"}"##));
+        assert!(expects_response(r#"{"profile":"A"}"#));
+        assert!(expects_response(r#"{"settings":"?"}"#));
+        assert!(expects_response(r#"{"save":true}"#));
+        assert!(expects_response(r#"{"load":true}"#));
+        assert!(!expects_response(r#"{"settings":{"debug":true}}"#));
+        assert!(!expects_response(r#"{"current":"A"}"#));
+        assert!(!expects_response("nonsense"));
+    }
+
+    #[test]
+    fn test_routing() {
+        let (mut knob, port) = TTYPort::pair().unwrap();
+        let device = Device::create(port);
+        let path = std::env::temp_dir().join(format!("bongoknob-test-{}.sock", std::process::id()));
+        let daemon = Daemon::bind(device, &path).unwrap();
+        let path = daemon.path().to_path_buf();
+        daemon.spawn();
+
+        // fake firmware: answer profile requests with the profile's name and
+        // refuse to switch profiles
+        thread::spawn(move || {
+            let mut buf = [0; 256];
+            let mut pending = String::new();
+            knob.set_timeout(Duration::from_secs(10)).unwrap();
+            while let Ok(n) = knob.read(&mut buf) {
+                pending.push_str(&String::from_utf8_lossy(&buf[..n]));
+                while let Some(end) = pending.find('\n') {
+                    let line: String = pending.drain(..=end).collect();
+                    let request: Value = serde_json::from_str(line.trim()).unwrap();
+                    if let Some(name) = request["current"].as_str() {
+                        writeln!(knob, "{}", json!({ "error": "refused", "msg": name })).unwrap();
+                        continue;
+                    }
+                    let name = request["profile"].as_str().unwrap();
+                    let reply = json!({ "profiles": [name], "current": name });
+                    writeln!(knob, "{}\n{{\"p\":7}}", reply).unwrap();
+                }
+            }
+        });
+
+        let clients: Vec<_> = (0..3)
+            .map(|i| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    let mut stream = UnixStream::connect(path).unwrap();
+                    stream
+                        .set_read_timeout(Some(Duration::from_secs(5)))
+                        .unwrap();
+                    let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+                    let name = format!("client {}", i);
+                    writeln!(stream, "{}", json!({ "current": name })).unwrap();
+                    writeln!(stream, "{}", json!({ "profile": name })).unwrap();
+
+                    let mut error = None;
+                    let mut answer = None;
+                    let mut events = 0;
+                    while error.is_none() || answer.is_none() || events == 0 {
+                        let line = lines.next().unwrap().unwrap();
+                        match Message::try_from(line.as_str()).unwrap() {
+                            Message::Error(e) => error = e.msg,
+                            Message::Profiles(p) => answer = Some(p.current_profile),
+                            Message::Event(_) => events += 1,
+                            m => panic!("unexpected message {}", m),
+                        }
+                    }
+                    assert_eq!(error.unwrap(), name);
+                    assert_eq!(answer.unwrap(), name);
+                })
+            })
+            .collect();
+        for client in clients {
+            client.join().unwrap();
+        }
+    }
+}
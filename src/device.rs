@@ -1,13 +1,21 @@
 use crate::error::Error;
-use crate::{protocol, Command, Message};
-use crossbeam::channel::{bounded, unbounded, Receiver, Sender};
+use crate::heartbeat::{HeartbeatState, Watchdog};
+use crate::knob_device::KnobDevice;
+use crate::responses::{Reply, ResponseQueue};
+use crate::subscription::{
+    Backpressure, Bus, Envelope, RawLine, Subscription, SubscriptionBuilder, SubscriptionOptions,
+};
+use crate::throttle::Throttled;
+use crate::{protocol, Command, KeyEvent, Message};
+use crossbeam::channel::{bounded, unbounded, Sender};
 use log::{error, info};
 use serialport::{DataBits, FlowControl, Parity, SerialPort, SerialPortInfo, StopBits, TTYPort};
 use std::fmt;
 use std::io::Read;
 use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Clone)]
 pub struct AvailableDevice {
@@ -31,6 +39,18 @@ impl fmt::Display for AvailableDevice {
 }
 
 impl AvailableDevice {
+    pub fn port_name(&self) -> &str {
+        &self.port_info.port_name
+    }
+
+    /// USB product string, if the port reports one.
+    pub fn product(&self) -> Option<&str> {
+        match &self.port_info.port_type {
+            serialport::SerialPortType::UsbPort(usb_info) => usb_info.product.as_deref(),
+            _ => None,
+        }
+    }
+
     pub fn set_timeout(&mut self, timeout: Duration) {
         self.timeout = timeout;
     }
@@ -73,42 +93,202 @@ pub fn discover() -> Result<Vec<AvailableDevice>, Error> {
 }
 
 pub fn connect(device: AvailableDevice) -> Result<Device, Error> {
-    info!("Connecting to device: {:?}", device.port_info.port_name);
-    let mut port = serialport::new(device.port_info.port_name, 115200)
-        .data_bits(DataBits::Eight)
-        .stop_bits(StopBits::One)
-        .parity(Parity::None)
-        .flow_control(FlowControl::None)
-        .open_native()?;
-
-    #[cfg(unix)]
-    port.set_exclusive(false)
-        .expect("Unable to set serial port exclusive to false");
-
-    port.set_timeout(device.timeout)
-        .expect("Failed to set port timeout");
-
-    Ok(Device::create(port))
+    ConnectOptions::from(device).connect()
+}
+
+/// Serial parameters used to open a knob.
+///
+/// Unlike [`connect`], this accepts any port path, so devices behind hubs or
+/// udev symlinks (e.g. `/dev/bongoknob-left`) can be opened directly.
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// let device = bongoknob::ConnectOptions::new("/dev/bongoknob-left")
+///     .baud_rate(115200)
+///     .timeout(Duration::from_millis(20))
+///     .dtr_on_open(true)
+///     .connect()?;
+/// # Ok::<(), bongoknob::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConnectOptions {
+    path: String,
+    baud_rate: u32,
+    data_bits: DataBits,
+    stop_bits: StopBits,
+    parity: Parity,
+    flow_control: FlowControl,
+    timeout: Duration,
+    exclusive: bool,
+    dtr_on_open: Option<bool>,
+    rts_on_open: Option<bool>,
+    command_capacity: Option<usize>,
+    subscription_options: SubscriptionOptions,
+}
+
+impl ConnectOptions {
+    /// Defaults to 115200 8N1 without flow control, a 10ms read timeout and
+    /// a non-exclusive lock, which is what the firmware expects.
+    pub fn new(path: impl Into<String>) -> Self {
+        ConnectOptions {
+            path: path.into(),
+            baud_rate: 115200,
+            data_bits: DataBits::Eight,
+            stop_bits: StopBits::One,
+            parity: Parity::None,
+            flow_control: FlowControl::None,
+            timeout: Duration::from_millis(10),
+            exclusive: false,
+            dtr_on_open: None,
+            rts_on_open: None,
+            command_capacity: None,
+            subscription_options: SubscriptionOptions::default(),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn baud_rate(mut self, baud_rate: u32) -> Self {
+        self.baud_rate = baud_rate;
+        self
+    }
+
+    pub fn data_bits(mut self, data_bits: DataBits) -> Self {
+        self.data_bits = data_bits;
+        self
+    }
+
+    pub fn stop_bits(mut self, stop_bits: StopBits) -> Self {
+        self.stop_bits = stop_bits;
+        self
+    }
+
+    pub fn parity(mut self, parity: Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    pub fn flow_control(mut self, flow_control: FlowControl) -> Self {
+        self.flow_control = flow_control;
+        self
+    }
+
+    /// Read timeout of the serial port, which is also how long the event loop
+    /// blocks before checking for pending commands.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Lock the port so no other process can open it (unix only).
+    pub fn exclusive(mut self, exclusive: bool) -> Self {
+        self.exclusive = exclusive;
+        self
+    }
+
+    /// Drive DTR to the given level right after opening. Left untouched if unset.
+    pub fn dtr_on_open(mut self, level: bool) -> Self {
+        self.dtr_on_open = Some(level);
+        self
+    }
+
+    /// Drive RTS to the given level right after opening. Left untouched if unset.
+    pub fn rts_on_open(mut self, level: bool) -> Self {
+        self.rts_on_open = Some(level);
+        self
+    }
+
+    /// Limit the number of queued commands. Sending blocks while the queue is full.
+    pub fn command_capacity(mut self, capacity: usize) -> Self {
+        self.command_capacity = Some(capacity);
+        self
+    }
+
+    /// Default queue length of subscriptions, `None` for unbounded.
+    pub fn subscription_capacity(mut self, capacity: Option<usize>) -> Self {
+        self.subscription_options.capacity = capacity;
+        self
+    }
+
+    /// Default policy for subscriptions whose queue is full.
+    pub fn backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.subscription_options.backpressure = backpressure;
+        self
+    }
+
+    pub fn connect(self) -> Result<Device, Error> {
+        info!("Connecting to device: {:?}", self.path);
+        let mut port = serialport::new(&self.path, self.baud_rate)
+            .data_bits(self.data_bits)
+            .stop_bits(self.stop_bits)
+            .parity(self.parity)
+            .flow_control(self.flow_control)
+            .timeout(self.timeout)
+            .open_native()?;
+
+        #[cfg(unix)]
+        port.set_exclusive(self.exclusive)?;
+
+        if let Some(level) = self.dtr_on_open {
+            port.write_data_terminal_ready(level)?;
+        }
+        if let Some(level) = self.rts_on_open {
+            port.write_request_to_send(level)?;
+        }
+
+        Ok(Device::create_with(
+            port,
+            self.command_capacity,
+            self.subscription_options,
+        ))
+    }
+}
+
+impl From<AvailableDevice> for ConnectOptions {
+    fn from(device: AvailableDevice) -> Self {
+        ConnectOptions::new(device.port_info.port_name).timeout(device.timeout)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Device {
-    messages: Receiver<Message>,
+    bus: Arc<Bus>,
+    subscription_options: SubscriptionOptions,
+    heartbeat: Arc<Mutex<HeartbeatState>>,
 
-    commands: Sender<(Command, Option<Sender<Result<Message, Error>>>)>,
+    commands: Sender<(Command, Reply)>,
 }
 
 impl Device {
-    pub fn create(mut port: TTYPort) -> Device {
-        let (msg_tx, msg_rx) = unbounded();
-        let (cmd_tx, cmd_rx) = unbounded::<(Command, Option<Sender<Result<Message, Error>>>)>();
+    pub fn create(port: TTYPort) -> Device {
+        Self::create_with(port, None, SubscriptionOptions::default())
+    }
+
+    /// Like [`create`](Device::create), but with a bounded command queue and
+    /// the default queue settings for new subscriptions.
+    pub fn create_with(
+        mut port: TTYPort,
+        command_capacity: Option<usize>,
+        subscription_options: SubscriptionOptions,
+    ) -> Device {
+        let (cmd_tx, cmd_rx) = match command_capacity {
+            Some(capacity) => bounded::<(Command, Reply)>(capacity),
+            None => unbounded(),
+        };
+
+        let bus = Arc::new(Bus::default());
+        let message_pipe = bus.clone();
+        let heartbeat = Arc::new(Mutex::new(HeartbeatState::default()));
+        let heartbeat_state = heartbeat.clone();
 
         thread::spawn(move || {
-            let message_pipe = msg_tx;
             let mut buffer = Vec::new();
-            let mut command_buffer = Vec::new();
+            let mut responses = ResponseQueue::default();
 
-            let mut line_buffer: Option<String> = None;
+            let mut line_buffer: Vec<String> = Vec::new();
 
             loop {
                 // process serial data from device
@@ -120,7 +300,7 @@ impl Device {
                             let line: Vec<u8> = buffer.drain(..=pos).collect::<Vec<_>>();
                             let line = String::from_utf8(line).unwrap();
 
-                            line_buffer = Some(line);
+                            line_buffer.push(line.trim_end().to_string());
                         }
                     }
                     Err(ref e) if e.kind() == io::ErrorKind::TimedOut => (),
@@ -129,14 +309,11 @@ impl Device {
 
                 // check if there's any commands to process
                 match cmd_rx.try_recv() {
-                    Ok((command, tx)) => {
+                    Ok((command, reply)) => {
                         let cmd = command.to_string();
 
-                        // add command response pipe to stack
-                        match tx {
-                            Some(tx) => command_buffer.push(tx),
-                            None => {}
-                        };
+                        // remember where the reply goes
+                        responses.push(reply, Instant::now());
                         // TODO fix unwraps here
                         port.write_all(cmd.as_bytes()).unwrap();
                         port.write_all(b"\n").unwrap();
@@ -150,108 +327,191 @@ impl Device {
                     },
                 }
 
-                // process buffered message
-                match line_buffer {
-                    Some(ref line) => {
-                        let message = protocol::Message::try_from(line.as_str());
-                        match message {
-                            Ok(message) => match message {
-                                Message::Heartbeat(_) | Message::Event(_) => {
-                                    message_pipe.send(message).unwrap();
-                                }
-                                Message::Error(e) => {
-                                    if command_buffer.len() > 0 {
-                                        command_buffer
-                                            .remove(0)
-                                            .send(Err(Error::CommandError(e.error, e.msg)))
-                                            .unwrap();
-                                    } else {
-                                        let err = Error::DeviceError(e.error, e.msg);
-                                        error!("device error: {}", err);
-                                    }
-                                }
-                                _ => {
-                                    if command_buffer.len() > 0 {
-                                        command_buffer.remove(0).send(Ok(message)).unwrap();
-                                    }
-                                }
-                            },
-                            Err(e) => {
-                                dbg!(&line);
-                                dbg!(&e);
-                                error!("could not parse message: {}", e);
-                            }
-                        }
-
-                        line_buffer = None;
+                // process buffered messages
+                for line in line_buffer.drain(..) {
+                    let received_at = Instant::now();
+                    let Some(envelope) = message_pipe.publish_line(line, received_at) else {
+                        continue;
+                    };
+
+                    if let Message::Heartbeat(heartbeat) = &envelope.message {
+                        heartbeat_state
+                            .lock()
+                            .unwrap()
+                            .record(heartbeat.clone(), received_at);
                     }
-                    None => {}
+                    responses.route(envelope);
                 }
             }
         });
 
         Device {
             commands: cmd_tx,
-            messages: msg_rx,
+            bus,
+            subscription_options,
+            heartbeat,
         }
     }
 
     pub fn command_response(&self, command: Command) -> Result<Message, Error> {
-        let (tx, rx) = bounded(1);
-        match self.commands.send((command, Some(tx))) {
-            Ok(_) => {}
-            Err(_) => return Err(Error::CommandSendError),
-        }
-        match rx.recv() {
-            Ok(msg) => msg,
-            Err(_) => Err(Error::CommandSendError),
+        match self.command_envelope(command)?.message {
+            Message::Error(e) => Err(Error::CommandError(e.error, e.msg)),
+            message => Ok(message),
         }
     }
 
+    /// Like [`command_response`](Device::command_response), keeping the line
+    /// the device answered with.
+    pub(crate) fn command_envelope(&self, command: Command) -> Result<Envelope, Error> {
+        let (tx, rx) = bounded(1);
+        self.send_command(command, Reply::response(tx))?;
+        rx.recv().map_err(|_| Error::CommandSendError)
+    }
+
+    pub(crate) fn send_command(&self, command: Command, reply: Reply) -> Result<(), Error> {
+        self.commands
+            .send((command, reply))
+            .map_err(|_| Error::CommandSendError)
+    }
+
+    /// Send a line to the device exactly as given. Responses arrive on
+    /// [`subscribe_raw`](Device::subscribe_raw) like everything else.
+    pub fn send_raw(&self, line: &str) -> Result<(), Error> {
+        self.command(Command::Raw(line.to_string()))
+    }
+
     pub fn command(&self, command: Command) -> Result<(), Error> {
-        match self.commands.send((command, None)) {
-            Ok(_) => Ok(()),
-            Err(_) => return Err(Error::CommandSendError),
-        }
+        let reply = Reply::ignore(&command);
+        self.send_command(command, reply)
+    }
+
+    /// Receive heartbeats and events. Every call returns an independent
+    /// subscription that gets its own copy of each message.
+    pub fn subscribe(&self) -> Subscription<Message> {
+        self.subscription().messages()
+    }
+
+    /// Configure the queue of a new subscription before choosing what it receives.
+    pub fn subscription(&self) -> SubscriptionBuilder<'_> {
+        SubscriptionBuilder::new(&self.bus, self.subscription_options)
+    }
+
+    /// Receive every parsed line, including command responses, stamped with
+    /// the time the reader thread parsed it and the raw line.
+    pub fn subscribe_envelopes(&self) -> Subscription<Envelope> {
+        self.subscription().envelopes()
+    }
+
+    /// Every line the device sends, including ones that don't parse.
+    pub fn subscribe_raw(&self) -> Subscription<RawLine> {
+        self.subscription().raw_lines()
+    }
+
+    /// Receive only knob positions.
+    pub fn subscribe_positions(&self) -> Subscription<u64> {
+        self.subscription().positions()
+    }
+
+    /// Receive key presses and releases for all keys.
+    pub fn subscribe_keys(&self) -> Subscription<KeyEvent> {
+        self.subscription().keys()
+    }
+
+    /// Receive key presses and releases for a single key.
+    pub fn subscribe_key(&self, id: u8) -> Subscription<KeyEvent> {
+        self.subscription().key(id)
+    }
+
+    pub fn subscribe_heartbeats(&self) -> Subscription<protocol::Heartbeat> {
+        self.subscription().heartbeats()
     }
 
-    pub fn subscribe(&self) -> Receiver<Message> {
-        self.messages.clone()
+    /// Receive errors reported by the device. This includes errors that are
+    /// also returned to the command that caused them.
+    pub fn subscribe_errors(&self) -> Subscription<protocol::DeviceError> {
+        self.subscription().errors()
+    }
+
+    /// Receive heartbeats and events, with positions coalesced to at most
+    /// `max_per_second`. See [`Throttled`]. The queue underneath is unbounded
+    /// so keys are never dropped while the consumer falls behind.
+    pub fn subscribe_throttled(&self, max_per_second: u32) -> Throttled {
+        Throttled::new(self.subscription().unbounded().messages(), max_per_second)
+    }
+
+    /// Messages dropped across all subscriptions because their queues were full.
+    pub fn dropped_messages(&self) -> u64 {
+        self.bus.dropped()
+    }
+
+    /// When the last heartbeat was received.
+    pub fn last_heartbeat(&self) -> Option<Instant> {
+        self.heartbeat.lock().unwrap().last_heartbeat()
+    }
+
+    /// Time since the knob was last touched, based on the last heartbeat.
+    pub fn idle_duration(&self) -> Option<Duration> {
+        self.heartbeat.lock().unwrap().idle(Instant::now())
+    }
+
+    /// Whether the knob has been untouched for longer than `idle_timeout`.
+    ///
+    /// The timeout is taken from the last [`get_settings`](Device::get_settings)
+    /// or [`set_settings`](Device::set_settings) call that included it. Until
+    /// then the knob is never considered idle.
+    pub fn is_idle(&self) -> bool {
+        self.heartbeat.lock().unwrap().is_idle(Instant::now())
+    }
+
+    /// Raise [`Liveness::Stale`] when no heartbeat arrives for `interval`, and
+    /// [`Liveness::Alive`] once they resume.
+    pub fn watch_heartbeat(&self, interval: Duration) -> Watchdog {
+        Watchdog::spawn(self.heartbeat.clone(), interval)
+    }
+
+    fn remember_settings(&self, settings: &protocol::Settings) {
+        if let Some(idle_timeout) = settings.idle_timeout {
+            self.heartbeat
+                .lock()
+                .unwrap()
+                .set_idle_timeout(Duration::from_millis(idle_timeout as u64));
+        }
     }
 
     // GET
     pub fn get_settings(&self) -> Result<protocol::Settings, Error> {
         let v = self.command_response(Command::GetSettings)?;
         match v {
-            Message::Settings(settings_root) => Ok(settings_root.settings),
+            Message::Settings(settings_root) => {
+                self.remember_settings(&settings_root.settings);
+                Ok(settings_root.settings)
+            }
             Message::Error(e) => Err(Error::CommandError(e.error, e.msg)),
             _ => Err(Error::UnexpectedResponse(v)),
         }
     }
 
     pub fn get_profiles(&self) -> Result<Vec<String>, Error> {
-        let v = self.command_response(Command::GetProfiles)?;
-        match v {
-            Message::Profiles(p) => match p.profiles {
-                Some(v) => return Ok(v),
-                None => return Ok(Vec::new()),
-            },
-            Message::Error(e) => Err(Error::CommandError(e.error, e.msg)),
-            _ => Err(Error::UnexpectedResponse(v)),
-        }
+        KnobDevice::get_profiles(self)
+    }
+
+    /// Name of the active profile.
+    pub fn get_current_profile(&self) -> Result<String, Error> {
+        KnobDevice::get_current_profile(self)
     }
 
     pub fn get_profile(&self, profile: &str) -> Result<protocol::Profile, Error> {
-        let v = self.command_response(Command::GetProfile(profile.to_string()))?;
-        match v {
-            Message::Profile(profile_root) => Ok(profile_root.profile),
-            Message::Error(e) => Err(Error::CommandError(e.error, e.msg)),
-            _ => Err(Error::UnexpectedResponse(v)),
-        }
+        KnobDevice::get_profile(self, profile)
     }
 
     // SET
+    /// Switch to another profile.
+    pub fn set_profile(&self, profile: &str) -> Result<(), Error> {
+        self.command(Command::SetProfile(profile.to_string()))
+    }
+
     pub fn set_settings(&self, data: protocol::Settings) -> Result<(), Error> {
+        self.remember_settings(&data);
         self.command(Command::SetSettings(data)).unwrap();
         Ok(())
     }
@@ -287,13 +547,15 @@ impl Device {
 
     /// Save the settings and profiles to SPIFFs
     pub fn save_settings(&self) -> Result<(), Error> {
-        self.command(Command::Save).unwrap();
-        Ok(())
+        match self.command_response(Command::Save)? {
+            Message::Saved(_) => Ok(()),
+            v => Err(Error::UnexpectedResponse(v)),
+        }
     }
 
     /// Reload the settings and profiles from SPIFFs
     pub fn load_settings(&self) -> Result<(), Error> {
-        self.command(Command::Load).unwrap();
+        self.command_response(Command::Load)?;
         Ok(())
     }
 
@@ -304,9 +566,33 @@ impl Device {
     }
 }
 
+impl KnobDevice for Device {
+    fn command_response(&self, command: Command) -> Result<Message, Error> {
+        Device::command_response(self, command)
+    }
+
+    fn command(&self, command: Command) -> Result<(), Error> {
+        Device::command(self, command)
+    }
+
+    fn subscription(&self) -> SubscriptionBuilder<'_> {
+        Device::subscription(self)
+    }
+
+    // these also keep track of the idle timeout
+    fn get_settings(&self) -> Result<protocol::Settings, Error> {
+        Device::get_settings(self)
+    }
+
+    fn set_settings(&self, data: protocol::Settings) -> Result<(), Error> {
+        Device::set_settings(self, data)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Event;
 
     #[test]
     fn test_commands() {
@@ -322,4 +608,31 @@ mod tests {
             Err(Error::CommandError(_, _))
         ));
     }
+
+    #[test]
+    fn test_throttled_slow_consumer() {
+        let (mut knob, port) = TTYPort::pair().unwrap();
+        let device = Device::create(port);
+        let mut events = device.subscribe_throttled(1000);
+
+        // a key, then more positions than a default queue holds
+        let mut lines = String::from("{\"kd\":1,\"ks\":2}\n");
+        for p in 0..2000 {
+            lines.push_str(&format!("{{\"p\":{}}}\n", p));
+        }
+        knob.write_all(lines.as_bytes()).unwrap();
+        // let the reader thread get ahead of us
+        thread::sleep(Duration::from_millis(500));
+
+        let mut key = false;
+        let mut last = None;
+        while last != Some(1999) {
+            match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+                Message::Event(Event::Key(_)) => key = true,
+                Message::Event(Event::Position(p)) => last = Some(p),
+                _ => {}
+            }
+        }
+        assert!(key);
+    }
 }
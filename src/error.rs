@@ -10,6 +10,8 @@ pub enum Error {
     ParseError(#[from] serde_json::Error),
     #[error("no devices found")]
     NoDevicesFound,
+    #[error("io error")]
+    Io(#[from] std::io::Error),
 
     //serial port stuff
     #[error("serial port error")]
@@ -20,6 +22,8 @@ pub enum Error {
     UnexpectedResponse(Message),
     #[error("conversion error: {0}")]
     ConversionError(String),
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
     // returned from device
     #[error("command error: {0} {1:?}")]
     CommandError(String, Option<String>),
@@ -0,0 +1,321 @@
+use crate::tracker::{PositionTracker, TrackerEvent};
+use crate::{Event, KeyEvent, Knob, Message};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+const KEYS: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Gesture {
+    /// Short press and release, after the double tap window passed
+    Tap { id: u8 },
+    /// Two taps on the same key within the double tap window
+    DoubleTap { id: u8 },
+    /// Key has been held past the long press threshold, emitted while still down
+    LongPress { id: u8 },
+    /// Key released after being held longer than a tap
+    Hold { id: u8, duration: Duration },
+    /// A key went down while others were already held
+    Chord { keys: [bool; 4] },
+    /// The knob moved while one or more keys were held
+    TurnWhileHeld { keys: [bool; 4], delta: i64 },
+}
+
+impl fmt::Display for Gesture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Gesture::Tap { id } => write!(f, "[TAP] Key: {}", id),
+            Gesture::DoubleTap { id } => write!(f, "[DOUBLE TAP] Key: {}", id),
+            Gesture::LongPress { id } => write!(f, "[LONG PRESS] Key: {}", id),
+            Gesture::Hold { id, duration } => {
+                write!(f, "[HOLD] Key: {}, Duration: {:?}", id, duration)
+            }
+            Gesture::Chord { keys } => write!(f, "[CHORD] State: {}", pretty_keys(keys)),
+            Gesture::TurnWhileHeld { keys, delta } => {
+                write!(f, "[TURN] State: {}, Delta: {:+}", pretty_keys(keys), delta)
+            }
+        }
+    }
+}
+
+fn pretty_keys(keys: &[bool; 4]) -> String {
+    KeyEvent::Down { keys: *keys, id: 0 }.pretty_print()
+}
+
+#[derive(Debug, Clone)]
+pub struct GestureConfig {
+    /// Longest press that still counts as a tap
+    pub tap_max: Duration,
+    /// How long to wait for a second tap. Zero reports taps immediately and
+    /// disables double taps.
+    pub double_tap_window: Duration,
+    /// How long a key has to be held to report a long press
+    pub long_press: Duration,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        GestureConfig {
+            tap_max: Duration::from_millis(250),
+            double_tap_window: Duration::from_millis(300),
+            long_press: Duration::from_millis(600),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct KeyState {
+    down_at: Option<Instant>,
+    long_pressed: bool,
+    /// Part of a chord or a turn, so releasing it is not a tap or hold
+    consumed: bool,
+    pending_tap: Option<Instant>,
+}
+
+/// Turns raw key and position events into higher level gestures.
+///
+/// Timed gestures (`Tap` after the double tap window, `LongPress`) need the
+/// clock to advance, so call [`poll`](GestureRecognizer::poll) periodically,
+/// for example whenever `recv_timeout` on the subscription times out.
+#[derive(Debug, Clone)]
+pub struct GestureRecognizer {
+    config: GestureConfig,
+    keys: [KeyState; KEYS],
+    tracker: PositionTracker,
+}
+
+impl Default for GestureRecognizer {
+    fn default() -> Self {
+        Self::new(GestureConfig::default())
+    }
+}
+
+impl GestureRecognizer {
+    pub fn new(config: GestureConfig) -> Self {
+        GestureRecognizer {
+            config,
+            keys: Default::default(),
+            tracker: PositionTracker::new(),
+        }
+    }
+
+    /// Apply the range and wrap configuration used for turn deltas.
+    pub fn set_knob(&mut self, knob: &Knob) {
+        self.tracker.set_knob(knob);
+    }
+
+    /// Keys currently held down, useful for shift style layers.
+    pub fn held(&self) -> [bool; 4] {
+        let mut held = [false; 4];
+        for (h, key) in held.iter_mut().zip(self.keys.iter()) {
+            *h = key.down_at.is_some();
+        }
+        held
+    }
+
+    pub fn handle(&mut self, message: &Message) -> Vec<Gesture> {
+        self.handle_at(message, Instant::now())
+    }
+
+    pub fn handle_at(&mut self, message: &Message, at: Instant) -> Vec<Gesture> {
+        let mut gestures = self.poll(at);
+        match message {
+            Message::Event(Event::Key(key)) => gestures.extend(self.key(key, at)),
+            Message::Event(Event::Position(position)) => {
+                if let Some(TrackerEvent::Rotated { delta, .. }) =
+                    self.tracker.update(*position, at)
+                {
+                    gestures.extend(self.turn(delta));
+                }
+            }
+            _ => {}
+        }
+        gestures
+    }
+
+    /// Emit gestures that became due because time passed.
+    pub fn poll(&mut self, now: Instant) -> Vec<Gesture> {
+        let mut gestures = Vec::new();
+        for (id, key) in self.keys.iter_mut().enumerate() {
+            if let Some(down_at) = key.down_at {
+                if !key.consumed
+                    && !key.long_pressed
+                    && now.saturating_duration_since(down_at) >= self.config.long_press
+                {
+                    key.long_pressed = true;
+                    gestures.push(Gesture::LongPress { id: id as u8 });
+                }
+            }
+            if let Some(tapped_at) = key.pending_tap {
+                if now.saturating_duration_since(tapped_at) > self.config.double_tap_window {
+                    key.pending_tap = None;
+                    gestures.push(Gesture::Tap { id: id as u8 });
+                }
+            }
+        }
+        gestures
+    }
+
+    fn key(&mut self, event: &KeyEvent, at: Instant) -> Vec<Gesture> {
+        let mut gestures = Vec::new();
+        match event {
+            KeyEvent::Down { id, .. } => {
+                let Some(key) = self.keys.get_mut(*id as usize) else {
+                    return gestures;
+                };
+                key.down_at = Some(at);
+                key.long_pressed = false;
+                key.consumed = false;
+
+                let held = self.held();
+                if held.iter().filter(|&&h| h).count() > 1 {
+                    for key in self.keys.iter_mut().filter(|k| k.down_at.is_some()) {
+                        key.consumed = true;
+                        // a tap just before joining the chord was its start
+                        key.pending_tap = None;
+                    }
+                    gestures.push(Gesture::Chord { keys: held });
+                }
+            }
+            KeyEvent::Up { id, .. } => {
+                let config = &self.config;
+                let Some(key) = self.keys.get_mut(*id as usize) else {
+                    return gestures;
+                };
+                let Some(down_at) = key.down_at.take() else {
+                    return gestures;
+                };
+                if key.consumed {
+                    return gestures;
+                }
+
+                let duration = at.saturating_duration_since(down_at);
+                if duration <= config.tap_max {
+                    match key.pending_tap.take() {
+                        Some(_) => gestures.push(Gesture::DoubleTap { id: *id }),
+                        None if config.double_tap_window.is_zero() => {
+                            gestures.push(Gesture::Tap { id: *id })
+                        }
+                        None => key.pending_tap = Some(at),
+                    }
+                } else {
+                    if duration >= config.long_press && !key.long_pressed {
+                        gestures.push(Gesture::LongPress { id: *id });
+                    }
+                    gestures.push(Gesture::Hold { id: *id, duration });
+                }
+            }
+        }
+        gestures
+    }
+
+    fn turn(&mut self, delta: i64) -> Option<Gesture> {
+        let held = self.held();
+        if !held.iter().any(|&h| h) {
+            return None;
+        }
+        for key in self.keys.iter_mut().filter(|k| k.down_at.is_some()) {
+            key.consumed = true;
+        }
+        Some(Gesture::TurnWhileHeld { keys: held, delta })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn down(id: u8) -> Message {
+        Message::Event(Event::Key(KeyEvent::Down {
+            keys: [false; 4],
+            id,
+        }))
+    }
+
+    fn up(id: u8) -> Message {
+        Message::Event(Event::Key(KeyEvent::Up {
+            keys: [false; 4],
+            id,
+        }))
+    }
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_tap_and_double_tap() {
+        let mut r = GestureRecognizer::default();
+        let t = Instant::now();
+
+        assert!(r.handle_at(&down(0), t).is_empty());
+        assert!(r.handle_at(&up(0), ms(t, 100)).is_empty());
+        assert_eq!(r.poll(ms(t, 500)), vec![Gesture::Tap { id: 0 }]);
+
+        r.handle_at(&down(1), ms(t, 1000));
+        r.handle_at(&up(1), ms(t, 1050));
+        r.handle_at(&down(1), ms(t, 1150));
+        assert_eq!(
+            r.handle_at(&up(1), ms(t, 1200)),
+            vec![Gesture::DoubleTap { id: 1 }]
+        );
+        assert!(r.poll(ms(t, 2000)).is_empty());
+    }
+
+    #[test]
+    fn test_long_press_and_hold() {
+        let mut r = GestureRecognizer::default();
+        let t = Instant::now();
+
+        r.handle_at(&down(2), t);
+        assert!(r.poll(ms(t, 400)).is_empty());
+        assert_eq!(r.poll(ms(t, 700)), vec![Gesture::LongPress { id: 2 }]);
+        assert!(r.poll(ms(t, 800)).is_empty());
+        assert_eq!(
+            r.handle_at(&up(2), ms(t, 900)),
+            vec![Gesture::Hold {
+                id: 2,
+                duration: Duration::from_millis(900)
+            }]
+        );
+    }
+
+    #[test]
+    fn test_chord_and_turn() {
+        let mut r = GestureRecognizer::default();
+        let t = Instant::now();
+
+        r.handle_at(&down(0), t);
+        assert_eq!(
+            r.handle_at(&down(3), ms(t, 20)),
+            vec![Gesture::Chord {
+                keys: [true, false, false, true]
+            }]
+        );
+        // chord members don't tap on release
+        assert!(r.handle_at(&up(0), ms(t, 100)).is_empty());
+        assert!(r.handle_at(&up(3), ms(t, 110)).is_empty());
+        assert!(r.poll(ms(t, 1000)).is_empty());
+
+        r.handle_at(&Message::Event(Event::Position(10)), ms(t, 1000));
+        r.handle_at(&down(1), ms(t, 1010));
+        assert_eq!(
+            r.handle_at(&Message::Event(Event::Position(12)), ms(t, 1020)),
+            vec![Gesture::TurnWhileHeld {
+                keys: [false, true, false, false],
+                delta: 2
+            }]
+        );
+        assert!(r.handle_at(&up(1), ms(t, 1100)).is_empty());
+
+        // nor does a tap that leads into a chord
+        r.handle_at(&down(2), ms(t, 2000));
+        r.handle_at(&up(2), ms(t, 2050));
+        r.handle_at(&down(2), ms(t, 2100));
+        let gestures = r.handle_at(&down(1), ms(t, 2110));
+        assert_eq!(gestures[0].to_string(), "[CHORD] State: [OXXO]");
+        r.handle_at(&up(2), ms(t, 2200));
+        r.handle_at(&up(1), ms(t, 2200));
+        assert!(r.poll(ms(t, 3000)).is_empty());
+    }
+}
@@ -0,0 +1,67 @@
+use crate::error::Error;
+use crate::Knob;
+
+impl Knob {
+    /// Check the value range, step and haptic start and end for consistency.
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |s: &str| Err(Error::InvalidConfig(s.to_string()));
+
+        if self.value_min >= self.value_max {
+            return invalid("value_min must be below value_max");
+        }
+        if self.angle_min > self.angle_max {
+            return invalid("angle_min must not be above angle_max");
+        }
+        if self.step == 0 || self.step > self.value_max - self.value_min {
+            return invalid("step must be between 1 and the value range");
+        }
+        if self.haptic.start_pos > self.haptic.end_pos {
+            return invalid("haptic start_pos must not be above end_pos");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, Haptic, Knob};
+
+    #[test]
+    fn test_validate() {
+        let mut knob = Knob {
+            value_min: 0,
+            value_max: 127,
+            angle_min: 0,
+            angle_max: 255,
+            wrap: false,
+            step: 1,
+            key_state: 0,
+            haptic: Haptic {
+                mode: 9,
+                start_pos: 0,
+                end_pos: 255,
+                detent_count: 12,
+                vernier: 0,
+                kx_force: false,
+                output_ramp: 10000,
+                detent_strength: 0,
+            },
+            knob_type: "cc".to_string(),
+            channel: 1,
+            cc: 7,
+        };
+        assert!(knob.validate().is_ok());
+
+        knob.step = 0;
+        assert!(matches!(knob.validate(), Err(Error::InvalidConfig(_))));
+        knob.step = 128;
+        assert!(knob.validate().is_err());
+        knob.step = 1;
+        knob.haptic.start_pos = 10;
+        knob.haptic.end_pos = 5;
+        assert!(knob.validate().is_err());
+        knob.haptic.end_pos = 10;
+        knob.value_max = 0;
+        assert!(knob.validate().is_err());
+    }
+}
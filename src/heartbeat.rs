@@ -0,0 +1,174 @@
+use crate::Heartbeat;
+use crossbeam::channel::{unbounded, Receiver};
+use std::fmt;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Liveness {
+    /// Heartbeats arrive again after the device went stale
+    Alive,
+    /// No heartbeat arrived for at least the watchdog interval
+    Stale { silent_for: Duration },
+}
+
+impl fmt::Display for Liveness {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Liveness::Alive => write!(f, "[ALIVE]"),
+            Liveness::Stale { silent_for } => write!(f, "[STALE] Silent for: {:?}", silent_for),
+        }
+    }
+}
+
+/// Heartbeat bookkeeping shared between the reader thread and the device.
+#[derive(Debug, Default)]
+pub(crate) struct HeartbeatState {
+    last: Option<(Instant, Heartbeat)>,
+    idle_timeout: Option<Duration>,
+}
+
+impl HeartbeatState {
+    pub(crate) fn record(&mut self, heartbeat: Heartbeat, received_at: Instant) {
+        self.last = Some((received_at, heartbeat));
+    }
+
+    pub(crate) fn set_idle_timeout(&mut self, idle_timeout: Duration) {
+        self.idle_timeout = Some(idle_timeout);
+    }
+
+    pub(crate) fn last_heartbeat(&self) -> Option<Instant> {
+        self.last.as_ref().map(|(at, _)| *at)
+    }
+
+    /// Idle time reported by the last heartbeat, extrapolated to `now`.
+    pub(crate) fn idle(&self, now: Instant) -> Option<Duration> {
+        self.last
+            .as_ref()
+            .map(|(at, heartbeat)| heartbeat.idle_duration() + now.saturating_duration_since(*at))
+    }
+
+    pub(crate) fn is_idle(&self, now: Instant) -> bool {
+        match (self.idle(now), self.idle_timeout) {
+            (Some(idle), Some(timeout)) => idle >= timeout,
+            _ => false,
+        }
+    }
+}
+
+/// Raises [`Liveness`] events when heartbeats stop and resume, see
+/// [`Device::watch_heartbeat`](crate::Device::watch_heartbeat).
+///
+/// Derefs to a [`Receiver`]. The watchdog thread stops when this is dropped.
+pub struct Watchdog {
+    receiver: Receiver<Liveness>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    pub(crate) fn spawn(state: Arc<Mutex<HeartbeatState>>, interval: Duration) -> Self {
+        let (tx, rx) = unbounded();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let started = Instant::now();
+        let check = (interval / 4).max(Duration::from_millis(10));
+
+        thread::spawn(move || {
+            let mut stale = false;
+            while !stopped.load(Ordering::Relaxed) {
+                let now = Instant::now();
+                let last = state.lock().unwrap().last_heartbeat().unwrap_or(started);
+                let silent_for = now.saturating_duration_since(last);
+
+                let event = if silent_for >= interval && !stale {
+                    stale = true;
+                    Some(Liveness::Stale { silent_for })
+                } else if silent_for < interval && stale {
+                    stale = false;
+                    Some(Liveness::Alive)
+                } else {
+                    None
+                };
+
+                if let Some(event) = event {
+                    if tx.send(event).is_err() {
+                        break;
+                    }
+                }
+                thread::sleep(check);
+            }
+        });
+
+        Watchdog { receiver: rx, stop }
+    }
+}
+
+impl Deref for Watchdog {
+    type Target = Receiver<Liveness>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.receiver
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Debug for Watchdog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watchdog")
+            .field("pending", &self.receiver.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idle() {
+        let mut state = HeartbeatState::default();
+        let start = Instant::now();
+        assert_eq!(state.idle(start), None);
+        assert!(!state.is_idle(start));
+
+        state.record(Heartbeat { idle: 4000 }, start);
+        assert_eq!(
+            state.idle(start + Duration::from_secs(1)),
+            Some(Duration::from_secs(5))
+        );
+        // unknown timeout is never idle
+        assert!(!state.is_idle(start + Duration::from_secs(1)));
+
+        state.set_idle_timeout(Duration::from_secs(5));
+        assert!(!state.is_idle(start));
+        assert!(state.is_idle(start + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_watchdog() {
+        let state = Arc::new(Mutex::new(HeartbeatState::default()));
+        let watchdog = Watchdog::spawn(state.clone(), Duration::from_millis(40));
+
+        assert!(matches!(
+            watchdog.recv_timeout(Duration::from_secs(1)),
+            Ok(Liveness::Stale { .. })
+        ));
+
+        state
+            .lock()
+            .unwrap()
+            .record(Heartbeat { idle: 0 }, Instant::now());
+        assert_eq!(
+            watchdog.recv_timeout(Duration::from_secs(1)),
+            Ok(Liveness::Alive)
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use crate::error::Error;
+use crate::protocol::{self, DeviceError, Heartbeat};
+use crate::subscription::{Envelope, RawLine, Subscription, SubscriptionBuilder};
+use crate::{Command, KeyEvent, Message};
+
+/// Operations shared by a [`Device`](crate::Device) on the serial port and a
+/// [`Client`](crate::Client) of the sharing daemon, so code can be written
+/// once for both.
+///
+/// Named `KnobDevice` because [`Knob`](crate::Knob) is the knob configuration
+/// in a profile.
+///
+/// ```no_run
+/// use bongoknob::{Client, KnobDevice};
+///
+/// fn show_profiles(knob: &dyn KnobDevice) -> Result<(), bongoknob::Error> {
+///     for profile in knob.get_profiles()? {
+///         println!("{}", profile);
+///     }
+///     Ok(())
+/// }
+///
+/// show_profiles(&Client::connect(bongoknob::default_socket_path())?)?;
+/// # Ok::<(), bongoknob::Error>(())
+/// ```
+pub trait KnobDevice {
+    /// Send a command and wait for the device to answer it.
+    fn command_response(&self, command: Command) -> Result<Message, Error>;
+
+    /// Send a command the device doesn't answer.
+    fn command(&self, command: Command) -> Result<(), Error>;
+
+    fn subscription(&self) -> SubscriptionBuilder<'_>;
+
+    /// Send a line to the device exactly as given.
+    fn send_raw(&self, line: &str) -> Result<(), Error> {
+        self.command(Command::Raw(line.to_string()))
+    }
+
+    fn subscribe(&self) -> Subscription<Message> {
+        self.subscription().messages()
+    }
+
+    fn subscribe_envelopes(&self) -> Subscription<Envelope> {
+        self.subscription().envelopes()
+    }
+
+    fn subscribe_raw(&self) -> Subscription<RawLine> {
+        self.subscription().raw_lines()
+    }
+
+    fn subscribe_positions(&self) -> Subscription<u64> {
+        self.subscription().positions()
+    }
+
+    fn subscribe_keys(&self) -> Subscription<KeyEvent> {
+        self.subscription().keys()
+    }
+
+    fn subscribe_key(&self, id: u8) -> Subscription<KeyEvent> {
+        self.subscription().key(id)
+    }
+
+    fn subscribe_heartbeats(&self) -> Subscription<Heartbeat> {
+        self.subscription().heartbeats()
+    }
+
+    fn subscribe_errors(&self) -> Subscription<DeviceError> {
+        self.subscription().errors()
+    }
+
+    fn get_settings(&self) -> Result<protocol::Settings, Error> {
+        let v = self.command_response(Command::GetSettings)?;
+        match v {
+            Message::Settings(settings_root) => Ok(settings_root.settings),
+            Message::Error(e) => Err(Error::CommandError(e.error, e.msg)),
+            _ => Err(Error::UnexpectedResponse(v)),
+        }
+    }
+
+    fn get_profiles(&self) -> Result<Vec<String>, Error> {
+        let v = self.command_response(Command::GetProfiles)?;
+        match v {
+            Message::Profiles(p) => Ok(p.profiles.unwrap_or_default()),
+            Message::Error(e) => Err(Error::CommandError(e.error, e.msg)),
+            _ => Err(Error::UnexpectedResponse(v)),
+        }
+    }
+
+    /// Name of the active profile.
+    fn get_current_profile(&self) -> Result<String, Error> {
+        let v = self.command_response(Command::GetProfiles)?;
+        match v {
+            Message::Profiles(p) => Ok(p.current_profile),
+            Message::Error(e) => Err(Error::CommandError(e.error, e.msg)),
+            _ => Err(Error::UnexpectedResponse(v)),
+        }
+    }
+
+    fn get_profile(&self, profile: &str) -> Result<protocol::Profile, Error> {
+        let v = self.command_response(Command::GetProfile(profile.to_string()))?;
+        match v {
+            Message::Profile(profile_root) => Ok(profile_root.profile),
+            Message::Error(e) => Err(Error::CommandError(e.error, e.msg)),
+            _ => Err(Error::UnexpectedResponse(v)),
+        }
+    }
+
+    /// Switch to another profile.
+    fn set_profile(&self, profile: &str) -> Result<(), Error> {
+        self.command(Command::SetProfile(profile.to_string()))
+    }
+
+    fn set_settings(&self, data: protocol::Settings) -> Result<(), Error> {
+        self.command(Command::SetSettings(data))
+    }
+
+    fn set_screen(&self, data: protocol::ScreenData) -> Result<(), Error> {
+        self.command(Command::SetScreen(data))
+    }
+
+    /// Show a message on the device screen, `duration` in seconds.
+    fn set_message(
+        &self,
+        title: Option<String>,
+        text: Option<String>,
+        duration: Option<f32>,
+    ) -> Result<(), Error> {
+        self.command(Command::ShowMessage(protocol::MessageDetails {
+            title,
+            text,
+            duration,
+        }))
+    }
+
+    /// Save the settings and profiles to SPIFFs
+    fn save_settings(&self) -> Result<(), Error> {
+        match self.command_response(Command::Save)? {
+            Message::Saved(_) => Ok(()),
+            v => Err(Error::UnexpectedResponse(v)),
+        }
+    }
+
+    /// Reload the settings and profiles from SPIFFs
+    fn load_settings(&self) -> Result<(), Error> {
+        self.command_response(Command::Load)?;
+        Ok(())
+    }
+
+    /// Reset motor calibration
+    fn recalibrate(&self) -> Result<(), Error> {
+        self.command(Command::Recalibrate)
+    }
+}
+
+/// A [`KnobDevice`] for tests that records commands and answers them from
+/// canned replies.
+#[cfg(test)]
+pub(crate) mod fake {
+    use super::*;
+    use crate::subscription::{Bus, SubscriptionOptions};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    pub(crate) struct FakeKnob {
+        pub bus: Arc<Bus>,
+        /// Every command sent, answered or not
+        pub commands: Arc<Mutex<Vec<Command>>>,
+        /// Replies by command line; anything else is answered with an error
+        replies: Arc<Mutex<HashMap<String, String>>>,
+    }
+
+    impl FakeKnob {
+        /// Answer `command` with the line `reply`.
+        pub fn reply(self, command: Command, reply: &str) -> Self {
+            self.replies
+                .lock()
+                .unwrap()
+                .insert(command.to_string(), reply.to_string());
+            self
+        }
+    }
+
+    impl KnobDevice for FakeKnob {
+        fn command_response(&self, command: Command) -> Result<Message, Error> {
+            let reply = self
+                .replies
+                .lock()
+                .unwrap()
+                .get(&command.to_string())
+                .cloned()
+                .unwrap_or_else(|| r#"{"error":"not found","msg":null}"#.to_string());
+            self.commands.lock().unwrap().push(command);
+            match Message::try_from(reply.as_str())? {
+                Message::Error(e) => Err(Error::CommandError(e.error, e.msg)),
+                message => Ok(message),
+            }
+        }
+
+        fn command(&self, command: Command) -> Result<(), Error> {
+            self.commands.lock().unwrap().push(command);
+            Ok(())
+        }
+
+        fn subscription(&self) -> SubscriptionBuilder<'_> {
+            SubscriptionBuilder::new(&self.bus, SubscriptionOptions::default())
+        }
+    }
+}
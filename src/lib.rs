@@ -1,7 +1,52 @@
+mod animation;
+mod client;
+mod color;
+mod daemon;
 mod device;
 mod error;
+mod gesture;
+mod haptic;
+mod heartbeat;
+mod knob_device;
+mod menu;
+mod midi;
+mod midi_routing;
+mod osc;
 mod protocol;
+mod responses;
+mod screen;
+mod screen_updater;
+#[cfg(feature = "server")]
+mod server;
+mod subscription;
+mod sysex;
+mod throttle;
+mod tracker;
+mod tuning;
 
-pub use device::{connect, discover, AvailableDevice, Device};
+pub use animation::{Animator, Breathing, FollowKnob, KeyPulse, Layer, Led, LedFrame, Rainbow};
+pub use client::Client;
+pub use color::{Gradient, Palette};
+pub use daemon::{default_socket_path, Daemon};
+pub use device::{connect, discover, AvailableDevice, ConnectOptions, Device};
 pub use error::Error;
+pub use gesture::{Gesture, GestureConfig, GestureRecognizer};
+pub use heartbeat::{Liveness, Watchdog};
+pub use knob_device::KnobDevice;
+pub use menu::{Menu, MenuNavigator};
+pub use midi::{KnobMapping, MidiMessage, MidiRecorder, MidiTranslator};
+pub use midi_routing::{MidiPort, MidiRouting, MidiSource};
+pub use osc::{OscArg, OscBridge, OscMessage};
 pub use protocol::*;
+pub use screen::{ScreenManager, Toast};
+pub use screen_updater::{Overflow, ScreenLimits, ScreenUpdater};
+pub use serialport::{DataBits, FlowControl, Parity, StopBits};
+#[cfg(feature = "server")]
+pub use server::Server;
+pub use subscription::{
+    Backpressure, Envelope, RawLine, Subscription, SubscriptionBuilder, SubscriptionOptions,
+};
+pub use sysex::SysExCodec;
+pub use throttle::Throttled;
+pub use tracker::{Direction, PositionTracker, TrackerEvent};
+pub use tuning::{TuningParameter, TuningSession};
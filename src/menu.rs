@@ -0,0 +1,581 @@
+use crate::tracker::{PositionTracker, TrackerEvent};
+use crate::{Event, KeyEvent, Knob, Message, ScreenData};
+use std::fmt;
+use std::ops::RangeInclusive;
+
+const VISIBLE_ROWS: usize = 4;
+const BACK_LABEL: &str = "..";
+
+type ChoiceCallback = Box<dyn FnMut(usize, &str) + Send>;
+
+enum Item {
+    Action {
+        label: String,
+        action: Box<dyn FnMut() + Send>,
+    },
+    Submenu(Menu),
+    Number {
+        label: String,
+        value: i64,
+        range: RangeInclusive<i64>,
+        step: i64,
+        on_change: Box<dyn FnMut(i64) + Send>,
+    },
+    Choice {
+        label: String,
+        options: Vec<String>,
+        selected: usize,
+        on_change: ChoiceCallback,
+    },
+    Toggle {
+        label: String,
+        value: bool,
+        on_change: Box<dyn FnMut(bool) + Send>,
+    },
+}
+
+impl Item {
+    fn label(&self) -> &str {
+        match self {
+            Item::Action { label, .. }
+            | Item::Number { label, .. }
+            | Item::Choice { label, .. }
+            | Item::Toggle { label, .. } => label,
+            Item::Submenu(menu) => &menu.title,
+        }
+    }
+
+    fn text(&self, editing: bool) -> String {
+        let value = match self {
+            Item::Action { label, .. } => return label.clone(),
+            Item::Submenu(menu) => return format!("{} >", menu.title),
+            Item::Number { value, .. } => value.to_string(),
+            Item::Choice {
+                options, selected, ..
+            } => options.get(*selected).cloned().unwrap_or_default(),
+            Item::Toggle { value, .. } => if *value { "On" } else { "Off" }.to_string(),
+        };
+        if editing {
+            format!("{}: [{}]", self.label(), value)
+        } else {
+            format!("{}: {}", self.label(), value)
+        }
+    }
+
+    /// Move the value of an editor by `steps`.
+    fn adjust(&mut self, steps: i64) {
+        match self {
+            Item::Number {
+                value,
+                range,
+                step,
+                on_change,
+                ..
+            } => {
+                let new = value
+                    .saturating_add(steps.saturating_mul(*step))
+                    .clamp(*range.start(), *range.end());
+                if new != *value {
+                    *value = new;
+                    on_change(new);
+                }
+            }
+            Item::Choice {
+                options,
+                selected,
+                on_change,
+                ..
+            } if !options.is_empty() => {
+                let new = (*selected as i64 + steps).clamp(0, options.len() as i64 - 1) as usize;
+                if new != *selected {
+                    *selected = new;
+                    on_change(new, &options[new]);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A list of entries shown on the device screen, see [`MenuNavigator`].
+///
+/// ```
+/// use bongoknob::Menu;
+///
+/// let menu = Menu::new("Settings")
+///     .number("Brightness", 128, 0..=255, 8, |v| println!("brightness {}", v))
+///     .choice("Mode", &["Relative", "Absolute"], 0, |_, name| println!("{}", name))
+///     .toggle("Click", true, |on| println!("click {}", on))
+///     .submenu(Menu::new("MIDI").number("Channel", 1, 1..=16, 1, |_| {}))
+///     .action("Save", || println!("saving"));
+/// ```
+pub struct Menu {
+    title: String,
+    items: Vec<Item>,
+}
+
+impl fmt::Debug for Menu {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Menu")
+            .field("title", &self.title)
+            .field(
+                "items",
+                &self.items.iter().map(Item::label).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl Menu {
+    pub fn new(title: impl Into<String>) -> Self {
+        Menu {
+            title: title.into(),
+            items: Vec::new(),
+        }
+    }
+
+    /// An entry that runs `action` when selected.
+    pub fn action(
+        mut self,
+        label: impl Into<String>,
+        action: impl FnMut() + Send + 'static,
+    ) -> Self {
+        self.items.push(Item::Action {
+            label: label.into(),
+            action: Box::new(action),
+        });
+        self
+    }
+
+    pub fn submenu(mut self, menu: Menu) -> Self {
+        self.items.push(Item::Submenu(menu));
+        self
+    }
+
+    /// A numeric editor. Turning the knob changes the value by `step` per
+    /// detent. A reversed range like `10..=0` is taken as `0..=10`.
+    pub fn number(
+        mut self,
+        label: impl Into<String>,
+        value: i64,
+        range: RangeInclusive<i64>,
+        step: i64,
+        on_change: impl FnMut(i64) + Send + 'static,
+    ) -> Self {
+        let (start, end) = range.into_inner();
+        let range = start.min(end)..=start.max(end);
+        self.items.push(Item::Number {
+            label: label.into(),
+            value: value.clamp(*range.start(), *range.end()),
+            range,
+            step: step.max(1),
+            on_change: Box::new(on_change),
+        });
+        self
+    }
+
+    /// Pick one of `options`. The callback receives the index and the option.
+    /// Without options there is nothing to pick and turning does nothing.
+    pub fn choice(
+        mut self,
+        label: impl Into<String>,
+        options: &[&str],
+        selected: usize,
+        on_change: impl FnMut(usize, &str) + Send + 'static,
+    ) -> Self {
+        self.items.push(Item::Choice {
+            label: label.into(),
+            options: options.iter().map(|o| o.to_string()).collect(),
+            selected: selected.min(options.len().saturating_sub(1)),
+            on_change: Box::new(on_change),
+        });
+        self
+    }
+
+    /// An on/off switch, flipped every time it is selected.
+    pub fn toggle(
+        mut self,
+        label: impl Into<String>,
+        value: bool,
+        on_change: impl FnMut(bool) + Send + 'static,
+    ) -> Self {
+        self.items.push(Item::Toggle {
+            label: label.into(),
+            value,
+            on_change: Box::new(on_change),
+        });
+        self
+    }
+}
+
+/// Drives a [`Menu`] from the knob and keys and renders it onto [`ScreenData`].
+///
+/// Turning the knob moves the cursor, or changes the value while a number or
+/// choice is being edited. The select key opens submenus, runs actions, flips
+/// toggles and starts or ends editing. Submenus get a `..` entry to go back,
+/// so the whole menu works with the knob and a single key. An optional back
+/// key leaves editing or the current submenu.
+///
+/// ```no_run
+/// use bongoknob::{Menu, MenuNavigator, ScreenLimits, ScreenUpdater};
+/// use std::time::Duration;
+///
+/// # let device: bongoknob::Device = unimplemented!();
+/// let menu = Menu::new("Settings").toggle("Click", true, |_| {});
+/// let mut nav = MenuNavigator::new(menu, 0).back_key(1);
+/// let limits = ScreenLimits { title: 12, line: 20 };
+/// let mut screen = ScreenUpdater::new(Duration::from_millis(30), limits);
+///
+/// screen.set(nav.render());
+/// for message in device.subscribe().iter() {
+///     if nav.handle(&message) {
+///         screen.set(nav.render());
+///     }
+///     screen.update(&device)?;
+/// }
+/// # Ok::<(), bongoknob::Error>(())
+/// ```
+pub struct MenuNavigator {
+    root: Menu,
+    /// Index of the submenu item at each level below the root
+    path: Vec<usize>,
+    /// Cursor row at each level, including the root
+    cursors: Vec<usize>,
+    editing: bool,
+    select_key: u8,
+    back_key: Option<u8>,
+    tracker: PositionTracker,
+    positions_per_step: i64,
+    accumulated: i64,
+}
+
+impl fmt::Debug for MenuNavigator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MenuNavigator")
+            .field("menu", &self.current().title)
+            .field("cursor", &self.cursor())
+            .field("editing", &self.editing)
+            .finish()
+    }
+}
+
+impl MenuNavigator {
+    pub fn new(menu: Menu, select_key: u8) -> Self {
+        MenuNavigator {
+            root: menu,
+            path: Vec::new(),
+            cursors: vec![0],
+            editing: false,
+            select_key,
+            back_key: None,
+            tracker: PositionTracker::new(),
+            positions_per_step: 1,
+            accumulated: 0,
+        }
+    }
+
+    pub fn back_key(mut self, id: u8) -> Self {
+        self.back_key = Some(id);
+        self
+    }
+
+    /// How far the knob position has to move for one step, to match the detents.
+    pub fn positions_per_step(mut self, positions: u32) -> Self {
+        self.positions_per_step = positions.max(1) as i64;
+        self
+    }
+
+    /// Apply the range and wrap configuration of the active knob.
+    pub fn set_knob(&mut self, knob: &Knob) {
+        self.tracker.set_knob(knob);
+    }
+
+    pub fn is_editing(&self) -> bool {
+        self.editing
+    }
+
+    /// Process a message from the device. Returns true if the screen needs redrawing.
+    pub fn handle(&mut self, message: &Message) -> bool {
+        match message {
+            Message::Event(Event::Position(_)) => {
+                let Some(TrackerEvent::Rotated { delta, .. }) = self.tracker.handle(message) else {
+                    return false;
+                };
+                self.accumulated += delta;
+                let steps = self.accumulated / self.positions_per_step;
+                self.accumulated %= self.positions_per_step;
+                steps != 0 && self.turn(steps)
+            }
+            Message::Event(Event::Key(KeyEvent::Down { id, .. })) => {
+                if *id == self.select_key {
+                    self.select();
+                    true
+                } else if Some(*id) == self.back_key {
+                    self.back()
+                } else {
+                    false
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Move the cursor, or the edited value, by `steps`. Returns true if anything changed.
+    pub fn turn(&mut self, steps: i64) -> bool {
+        if self.editing {
+            let index = self.item_index();
+            if let Some(item) = index.and_then(|i| self.current_mut().items.get_mut(i)) {
+                item.adjust(steps);
+            }
+            return true;
+        }
+
+        let rows = self.rows() as i64;
+        let cursor = self.cursors.last_mut().unwrap();
+        let new = (*cursor as i64 + steps).clamp(0, (rows - 1).max(0)) as usize;
+        let changed = new != *cursor;
+        *cursor = new;
+        changed
+    }
+
+    /// Act on the entry under the cursor.
+    pub fn select(&mut self) {
+        if self.editing {
+            self.editing = false;
+            return;
+        }
+        let Some(index) = self.item_index() else {
+            self.back();
+            return;
+        };
+
+        let Some(item) = self.current_mut().items.get_mut(index) else {
+            return;
+        };
+        match item {
+            Item::Action { action, .. } => action(),
+            Item::Submenu(_) => {
+                self.path.push(index);
+                self.cursors.push(0);
+            }
+            Item::Number { .. } | Item::Choice { .. } => self.editing = true,
+            Item::Toggle {
+                value, on_change, ..
+            } => {
+                *value = !*value;
+                on_change(*value);
+            }
+        }
+    }
+
+    /// Leave editing, or go up one menu. Returns false at the top level.
+    pub fn back(&mut self) -> bool {
+        if self.editing {
+            self.editing = false;
+            return true;
+        }
+        if self.path.pop().is_some() {
+            self.cursors.pop();
+            return true;
+        }
+        false
+    }
+
+    /// The current menu level as a title and four visible rows.
+    pub fn render(&self) -> ScreenData {
+        let menu = self.current();
+        let cursor = self.cursor();
+        let rows = self.rows();
+        let first = cursor
+            .saturating_sub(VISIBLE_ROWS - 1)
+            .min(rows.saturating_sub(VISIBLE_ROWS));
+
+        let mut lines = (first..first + VISIBLE_ROWS).map(|row| {
+            if row >= rows {
+                return String::new();
+            }
+            let marker = if row == cursor { '>' } else { ' ' };
+            let text = match self.row_item(row) {
+                Some(index) => menu.items[index].text(self.editing && row == cursor),
+                None => BACK_LABEL.to_string(),
+            };
+            format!("{}{}", marker, text)
+        });
+
+        ScreenData {
+            title: Some(menu.title.clone()),
+            data1: lines.next(),
+            data2: lines.next(),
+            data3: lines.next(),
+            data4: lines.next(),
+        }
+    }
+
+    fn current(&self) -> &Menu {
+        let mut menu = &self.root;
+        for &index in &self.path {
+            menu = match &menu.items[index] {
+                Item::Submenu(sub) => sub,
+                _ => unreachable!("menu path only holds submenus"),
+            };
+        }
+        menu
+    }
+
+    fn current_mut(&mut self) -> &mut Menu {
+        let mut menu = &mut self.root;
+        for &index in &self.path {
+            menu = match &mut menu.items[index] {
+                Item::Submenu(sub) => sub,
+                _ => unreachable!("menu path only holds submenus"),
+            };
+        }
+        menu
+    }
+
+    fn cursor(&self) -> usize {
+        *self.cursors.last().unwrap()
+    }
+
+    /// Submenus start with a back entry.
+    fn has_back_row(&self) -> bool {
+        !self.path.is_empty()
+    }
+
+    fn rows(&self) -> usize {
+        self.current().items.len() + self.has_back_row() as usize
+    }
+
+    fn row_item(&self, row: usize) -> Option<usize> {
+        match self.has_back_row() {
+            true => row.checked_sub(1),
+            false => Some(row),
+        }
+    }
+
+    fn item_index(&self) -> Option<usize> {
+        self.row_item(self.cursor())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn down(id: u8) -> Message {
+        Message::Event(Event::Key(KeyEvent::Down {
+            keys: [false; 4],
+            id,
+        }))
+    }
+
+    #[test]
+    fn test_navigation_and_editors() {
+        let volume = Arc::new(Mutex::new(0));
+        let saved = Arc::new(Mutex::new(false));
+        let (v, s) = (volume.clone(), saved.clone());
+
+        let menu = Menu::new("Main")
+            .number("Volume", 5, 0..=10, 2, move |value| {
+                *v.lock().unwrap() = value
+            })
+            .submenu(Menu::new("More").action("Save", move || *s.lock().unwrap() = true));
+        let mut nav = MenuNavigator::new(menu, 0);
+
+        let screen = nav.render();
+        assert_eq!(screen.title.as_deref(), Some("Main"));
+        assert_eq!(screen.data1.as_deref(), Some(">Volume: 5"));
+        assert_eq!(screen.data2.as_deref(), Some(" More >"));
+        assert_eq!(screen.data3.as_deref(), Some(""));
+
+        // edit the number
+        nav.handle(&down(0));
+        assert!(nav.is_editing());
+        nav.turn(2);
+        assert_eq!(*volume.lock().unwrap(), 9);
+        nav.turn(5);
+        assert_eq!(*volume.lock().unwrap(), 10);
+        assert_eq!(nav.render().data1.as_deref(), Some(">Volume: [10]"));
+        nav.handle(&down(0));
+        assert!(!nav.is_editing());
+
+        // into the submenu and run the action
+        nav.turn(1);
+        nav.handle(&down(0));
+        let screen = nav.render();
+        assert_eq!(screen.title.as_deref(), Some("More"));
+        assert_eq!(screen.data1.as_deref(), Some(">.."));
+        nav.turn(1);
+        nav.handle(&down(0));
+        assert!(*saved.lock().unwrap());
+
+        // the back entry returns to the parent
+        nav.turn(-1);
+        nav.handle(&down(0));
+        assert_eq!(nav.render().title.as_deref(), Some("Main"));
+    }
+
+    #[test]
+    fn test_empty_editors() {
+        let changes = Arc::new(Mutex::new(0));
+        let (c1, c2) = (changes.clone(), changes.clone());
+        let menu = Menu::new("Main")
+            .number("Reversed", 3, RangeInclusive::new(10, 0), 1, |_| {})
+            .number("Huge", 0, i64::MIN..=i64::MAX, i64::MAX, move |_| {
+                *c1.lock().unwrap() += 1
+            })
+            .choice("Nothing", &[], 0, move |_, _| *c2.lock().unwrap() += 1);
+        let mut nav = MenuNavigator::new(menu, 0);
+
+        nav.handle(&down(0));
+        nav.turn(4);
+        assert_eq!(nav.render().data1.as_deref(), Some(">Reversed: [7]"));
+        nav.turn(-8);
+        assert_eq!(nav.render().data1.as_deref(), Some(">Reversed: [0]"));
+        nav.handle(&down(0));
+
+        nav.turn(1);
+        nav.handle(&down(0));
+        nav.turn(3);
+        assert_eq!(
+            nav.render().data2.as_deref(),
+            Some(format!(">Huge: [{}]", i64::MAX).as_str())
+        );
+        nav.turn(-1);
+        nav.turn(-3);
+        assert_eq!(
+            nav.render().data2.as_deref(),
+            Some(format!(">Huge: [{}]", i64::MIN).as_str())
+        );
+        nav.handle(&down(0));
+
+        nav.turn(1);
+        nav.handle(&down(0));
+        nav.turn(1);
+        nav.turn(-1);
+        assert_eq!(nav.render().data3.as_deref(), Some(">Nothing: []"));
+        assert_eq!(*changes.lock().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_knob_scrolling() {
+        let mut menu = Menu::new("List");
+        for i in 0..6 {
+            menu = menu.toggle(format!("Item {}", i), false, |_| {});
+        }
+        let mut nav = MenuNavigator::new(menu, 0).positions_per_step(2);
+
+        nav.handle(&Message::Event(Event::Position(100)));
+        assert!(!nav.handle(&Message::Event(Event::Position(101))));
+        assert!(nav.handle(&Message::Event(Event::Position(110))));
+
+        // cursor on the last row, the list scrolled to keep it visible
+        let screen = nav.render();
+        assert_eq!(screen.data1.as_deref(), Some(" Item 2: Off"));
+        assert_eq!(screen.data4.as_deref(), Some(">Item 5: Off"));
+
+        nav.handle(&down(0));
+        assert_eq!(nav.render().data4.as_deref(), Some(">Item 5: On"));
+    }
+}
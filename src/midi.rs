@@ -0,0 +1,504 @@
+use crate::error::Error;
+use crate::subscription::Envelope;
+use crate::{Event, KeyEvent, Knob, Message};
+use std::fmt;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// A MIDI channel voice message. Channels are 0-15 as on the wire, one less
+/// than [`Knob::channel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MidiMessage {
+    NoteOff {
+        channel: u8,
+        key: u8,
+        velocity: u8,
+    },
+    NoteOn {
+        channel: u8,
+        key: u8,
+        velocity: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    /// 14-bit value, 8192 is centered
+    PitchBend {
+        channel: u8,
+        value: u16,
+    },
+}
+
+impl MidiMessage {
+    pub fn bytes(&self) -> [u8; 3] {
+        match *self {
+            MidiMessage::NoteOff {
+                channel,
+                key,
+                velocity,
+            } => [0x80 | channel & 0x0f, key & 0x7f, velocity & 0x7f],
+            MidiMessage::NoteOn {
+                channel,
+                key,
+                velocity,
+            } => [0x90 | channel & 0x0f, key & 0x7f, velocity & 0x7f],
+            MidiMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            } => [0xb0 | channel & 0x0f, controller & 0x7f, value & 0x7f],
+            MidiMessage::PitchBend { channel, value } => [
+                0xe0 | channel & 0x0f,
+                (value & 0x7f) as u8,
+                (value >> 7 & 0x7f) as u8,
+            ],
+        }
+    }
+}
+
+impl fmt::Display for MidiMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MidiMessage::NoteOff { channel, key, .. } => {
+                write!(f, "[CH{}] Note off: {}", channel + 1, key)
+            }
+            MidiMessage::NoteOn {
+                channel,
+                key,
+                velocity,
+            } => write!(f, "[CH{}] Note on: {} ({})", channel + 1, key, velocity),
+            MidiMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            } => write!(f, "[CH{}] CC {}: {}", channel + 1, controller, value),
+            MidiMessage::PitchBend { channel, value } => {
+                write!(f, "[CH{}] Pitch bend: {}", channel + 1, value)
+            }
+        }
+    }
+}
+
+/// What the knob position is sent as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KnobMapping {
+    /// 7-bit value on a controller
+    ControlChange(u8),
+    /// 14-bit pitch bend
+    PitchBend,
+    /// 14-bit value on non-registered parameter 0 to 16383
+    Nrpn(u16),
+}
+
+impl KnobMapping {
+    /// The mapping a knob is configured for. The firmware doesn't document
+    /// its `knob_type` values; the only one known here is `cc`, sending on
+    /// controller `cc`. Other types are an error, pick a mapping with
+    /// [`MidiTranslator::with_mapping`] for those.
+    pub fn from_knob(knob: &Knob) -> Result<Self, Error> {
+        match knob.knob_type.as_str() {
+            "cc" => Ok(KnobMapping::ControlChange(knob.cc)),
+            other => Err(Error::ConversionError(format!(
+                "unknown knob type `{}`",
+                other
+            ))),
+        }
+    }
+
+    fn resolution(&self) -> u16 {
+        match self {
+            KnobMapping::ControlChange(_) => 0x7f,
+            KnobMapping::PitchBend | KnobMapping::Nrpn(_) => 0x3fff,
+        }
+    }
+}
+
+/// Turns position and key events into MIDI messages following a [`Knob`]'s
+/// output configuration.
+///
+/// The position is scaled from `value_min..=value_max` to the range of the
+/// mapping, and keys play notes from middle C up.
+///
+/// ```
+/// use bongoknob::{Haptic, Knob, Message, MidiMessage, MidiTranslator};
+///
+/// let knob = Knob {
+///     value_min: 0,
+///     value_max: 127,
+///     knob_type: "cc".to_string(),
+///     channel: 1,
+///     cc: 7,
+///     // ...
+/// #   angle_min: 0,
+/// #   angle_max: 255,
+/// #   wrap: false,
+/// #   step: 1,
+/// #   key_state: 0,
+/// #   haptic: Haptic {
+/// #       mode: 0,
+/// #       start_pos: 0,
+/// #       end_pos: 255,
+/// #       detent_count: 0,
+/// #       vernier: 0,
+/// #       kx_force: false,
+/// #       output_ramp: 0,
+/// #       detent_strength: 0,
+/// #   },
+/// };
+/// let mut translator = MidiTranslator::new(&knob)?;
+/// let message = Message::try_from(r#"{"p":255}"#)?;
+/// assert_eq!(
+///     translator.translate(&message),
+///     vec![MidiMessage::ControlChange { channel: 0, controller: 7, value: 127 }]
+/// );
+/// # Ok::<(), bongoknob::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct MidiTranslator {
+    channel: u8,
+    mapping: KnobMapping,
+    range: (u64, u64),
+    first_note: u8,
+    velocity: u8,
+    last_value: Option<u16>,
+}
+
+impl MidiTranslator {
+    /// Follow the output configuration of `knob`, see [`KnobMapping::from_knob`].
+    pub fn new(knob: &Knob) -> Result<Self, Error> {
+        Ok(Self::with_mapping(knob, KnobMapping::from_knob(knob)?))
+    }
+
+    /// Use the channel and value range of `knob`, but send the position as
+    /// `mapping`.
+    pub fn with_mapping(knob: &Knob, mapping: KnobMapping) -> Self {
+        let (min, max) = (knob.value_min as u64, knob.value_max as u64);
+        MidiTranslator {
+            channel: knob.channel.clamp(1, 16) - 1,
+            mapping,
+            range: (min.min(max), min.max(max)),
+            first_note: 60,
+            velocity: 100,
+            last_value: None,
+        }
+    }
+
+    /// Send the position as something other than what the knob is configured for.
+    pub fn mapping(mut self, mapping: KnobMapping) -> Self {
+        self.mapping = mapping;
+        self.last_value = None;
+        self
+    }
+
+    /// Note played by the first key, the others follow chromatically.
+    pub fn first_note(mut self, note: u8) -> Self {
+        self.first_note = note.min(0x7f);
+        self
+    }
+
+    pub fn velocity(mut self, velocity: u8) -> Self {
+        self.velocity = velocity.clamp(1, 0x7f);
+        self
+    }
+
+    /// Follow the output configuration of `knob`, e.g. after switching
+    /// profiles. Nothing changes if its type is unknown.
+    pub fn set_knob(&mut self, knob: &Knob) -> Result<(), Error> {
+        let mapping = KnobMapping::from_knob(knob)?;
+        *self = Self::with_mapping(knob, mapping)
+            .first_note(self.first_note)
+            .velocity(self.velocity);
+        Ok(())
+    }
+
+    /// MIDI messages for a message from the device. Positions that scale to
+    /// the value last sent produce nothing.
+    pub fn translate(&mut self, message: &Message) -> Vec<MidiMessage> {
+        match message {
+            Message::Event(Event::Position(position)) => self.position(*position),
+            Message::Event(Event::Key(key)) => vec![self.key(key)],
+            _ => Vec::new(),
+        }
+    }
+
+    fn position(&mut self, position: u64) -> Vec<MidiMessage> {
+        let (min, max) = self.range;
+        let resolution = self.mapping.resolution();
+        let value = if max > min {
+            let ratio = (position.clamp(min, max) - min) as f64 / (max - min) as f64;
+            (ratio * resolution as f64).round() as u16
+        } else {
+            0
+        };
+        if self.last_value.replace(value) == Some(value) {
+            return Vec::new();
+        }
+
+        let channel = self.channel;
+        let cc = |controller: u8, value: u16| MidiMessage::ControlChange {
+            channel,
+            controller,
+            value: (value & 0x7f) as u8,
+        };
+        match self.mapping {
+            KnobMapping::ControlChange(controller) => vec![cc(controller, value)],
+            KnobMapping::PitchBend => vec![MidiMessage::PitchBend { channel, value }],
+            KnobMapping::Nrpn(parameter) => vec![
+                cc(99, parameter >> 7),
+                cc(98, parameter),
+                cc(6, value >> 7),
+                cc(38, value),
+            ],
+        }
+    }
+
+    fn key(&self, key: &KeyEvent) -> MidiMessage {
+        let channel = self.channel;
+        let note = self.first_note.saturating_add(key.id()).min(0x7f);
+        match key {
+            KeyEvent::Down { .. } => MidiMessage::NoteOn {
+                channel,
+                key: note,
+                velocity: self.velocity,
+            },
+            KeyEvent::Up { .. } => MidiMessage::NoteOff {
+                channel,
+                key: note,
+                velocity: 0,
+            },
+        }
+    }
+}
+
+/// Ticks per quarter note in written files.
+const TICKS_PER_QUARTER: u16 = 480;
+/// Microseconds per quarter note, 120 BPM.
+const TEMPO: u32 = 500_000;
+
+/// Captures translated messages with their timing for non-realtime tools.
+///
+/// ```no_run
+/// use bongoknob::{KnobDevice, MidiRecorder, MidiTranslator};
+///
+/// let device = bongoknob::connect(bongoknob::discover()?.remove(0))?;
+/// let profile = device.get_profile(&device.get_current_profile()?)?;
+/// let knob = profile.knob.and_then(|k| k.into_iter().next()).unwrap();
+///
+/// let mut recorder = MidiRecorder::new(MidiTranslator::new(&knob)?);
+/// for envelope in device.subscribe_envelopes().iter().take(500) {
+///     recorder.record(&envelope);
+/// }
+/// recorder.write_smf(std::fs::File::create("performance.mid")?)?;
+/// # Ok::<(), bongoknob::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct MidiRecorder {
+    translator: MidiTranslator,
+    started: Option<Instant>,
+    events: Vec<(Duration, MidiMessage)>,
+}
+
+impl MidiRecorder {
+    pub fn new(translator: MidiTranslator) -> Self {
+        MidiRecorder {
+            translator,
+            started: None,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn translator_mut(&mut self) -> &mut MidiTranslator {
+        &mut self.translator
+    }
+
+    /// Record a message at the time it was received.
+    pub fn record(&mut self, envelope: &Envelope) {
+        self.record_at(&envelope.message, envelope.received_at);
+    }
+
+    /// Record a message at `at`. Time starts at the first message recorded.
+    pub fn record_at(&mut self, message: &Message, at: Instant) {
+        let started = *self.started.get_or_insert(at);
+        let offset = at.saturating_duration_since(started);
+        for midi in self.translator.translate(message) {
+            self.events.push((offset, midi));
+        }
+    }
+
+    /// Recorded messages and their offset from the start of the recording.
+    pub fn events(&self) -> &[(Duration, MidiMessage)] {
+        &self.events
+    }
+
+    pub fn clear(&mut self) {
+        self.started = None;
+        self.events.clear();
+    }
+
+    /// Write the messages back to back, without timing.
+    pub fn write_stream(&self, mut out: impl Write) -> Result<(), Error> {
+        for (_, message) in &self.events {
+            out.write_all(&message.bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Write a single track (format 0) Standard MIDI File at 120 BPM.
+    pub fn write_smf(&self, mut out: impl Write) -> Result<(), Error> {
+        let ticks_per_second = TICKS_PER_QUARTER as f64 * 1_000_000.0 / TEMPO as f64;
+        let mut track = Vec::new();
+        write_varlen(&mut track, 0);
+        track.extend_from_slice(&[0xff, 0x51, 0x03]);
+        track.extend_from_slice(&TEMPO.to_be_bytes()[1..]);
+
+        let mut last_tick = 0;
+        for (offset, message) in &self.events {
+            let tick = (offset.as_secs_f64() * ticks_per_second).round() as u32;
+            write_varlen(&mut track, tick.saturating_sub(last_tick));
+            track.extend_from_slice(&message.bytes());
+            last_tick = tick.max(last_tick);
+        }
+        write_varlen(&mut track, 0);
+        track.extend_from_slice(&[0xff, 0x2f, 0x00]);
+
+        out.write_all(b"MThd")?;
+        out.write_all(&6u32.to_be_bytes())?;
+        out.write_all(&0u16.to_be_bytes())?; // format
+        out.write_all(&1u16.to_be_bytes())?; // tracks
+        out.write_all(&TICKS_PER_QUARTER.to_be_bytes())?;
+        out.write_all(b"MTrk")?;
+        out.write_all(&(track.len() as u32).to_be_bytes())?;
+        out.write_all(&track)?;
+        Ok(())
+    }
+}
+
+/// Write a variable length quantity, seven bits per byte, most significant first.
+fn write_varlen(out: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        bytes.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    out.extend(bytes.iter().rev());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Haptic;
+
+    fn knob(knob_type: &str) -> Knob {
+        Knob {
+            value_min: 0,
+            value_max: 100,
+            angle_min: 0,
+            angle_max: 255,
+            wrap: false,
+            step: 1,
+            key_state: 0,
+            haptic: Haptic {
+                mode: 0,
+                start_pos: 0,
+                end_pos: 255,
+                detent_count: 0,
+                vernier: 0,
+                kx_force: false,
+                output_ramp: 0,
+                detent_strength: 0,
+            },
+            knob_type: knob_type.to_string(),
+            channel: 2,
+            cc: 44,
+        }
+    }
+
+    fn message(raw: &str) -> Message {
+        Message::try_from(raw).unwrap()
+    }
+
+    #[test]
+    fn test_translate() {
+        let mut translator = MidiTranslator::new(&knob("cc")).unwrap();
+        assert_eq!(
+            translator.translate(&message(r#"{"p":50}"#)),
+            vec![MidiMessage::ControlChange {
+                channel: 1,
+                controller: 44,
+                value: 64
+            }]
+        );
+        assert!(translator.translate(&message(r#"{"p":50}"#)).is_empty());
+        assert_eq!(
+            translator.translate(&message(r#"{"kd":1,"ks":2}"#)),
+            vec![MidiMessage::NoteOn {
+                channel: 1,
+                key: 61,
+                velocity: 100
+            }]
+        );
+        assert_eq!(
+            translator.translate(&message(r#"{"ku":1,"ks":0}"#))[0].bytes(),
+            [0x81, 61, 0]
+        );
+
+        assert!(MidiTranslator::new(&knob("pb")).is_err());
+        let mut translator = MidiTranslator::with_mapping(&knob("pb"), KnobMapping::PitchBend);
+        let bend = translator.translate(&message(r#"{"p":100}"#));
+        assert_eq!(bend[0].bytes(), [0xe1, 0x7f, 0x7f]);
+
+        let mut translator = MidiTranslator::new(&knob("cc"))
+            .unwrap()
+            .mapping(KnobMapping::Nrpn(1000));
+        let nrpn: Vec<_> = translator
+            .translate(&message(r#"{"p":50}"#))
+            .iter()
+            .map(MidiMessage::bytes)
+            .collect();
+        // 8192 is 0x40 0x00 in two seven bit halves
+        assert_eq!(
+            nrpn,
+            [
+                [0xb1, 99, 7],
+                [0xb1, 98, 104],
+                [0xb1, 6, 0x40],
+                [0xb1, 38, 0]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_smf() {
+        let mut recorder = MidiRecorder::new(MidiTranslator::new(&knob("cc")).unwrap());
+        let start = Instant::now();
+        recorder.record_at(&message(r#"{"p":0}"#), start);
+        recorder.record_at(&message(r#"{"p":100}"#), start + Duration::from_secs(1));
+
+        let mut smf = Vec::new();
+        recorder.write_smf(&mut smf).unwrap();
+        assert_eq!(&smf[..14], b"MThd\0\0\0\x06\0\0\0\x01\x01\xe0");
+        assert_eq!(&smf[14..18], b"MTrk");
+        let track = &smf[22..];
+        assert_eq!(
+            track.len() as u32,
+            u32::from_be_bytes(smf[18..22].try_into().unwrap())
+        );
+        // tempo, then two controller changes one second (960 ticks) apart
+        assert_eq!(
+            track,
+            [
+                0x00, 0xff, 0x51, 0x03, 0x07, 0xa1, 0x20, //
+                0x00, 0xb1, 44, 0, //
+                0x87, 0x40, 0xb1, 44, 127, //
+                0x00, 0xff, 0x2f, 0x00
+            ]
+        );
+
+        let mut stream = Vec::new();
+        recorder.write_stream(&mut stream).unwrap();
+        assert_eq!(stream, [0xb1, 44, 0, 0xb1, 44, 127]);
+    }
+}
@@ -0,0 +1,294 @@
+use crate::error::Error;
+use crate::{MidiSettings, Settings};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MidiPort {
+    /// USB MIDI, `midiUsb` in the settings
+    Usb,
+    /// The DIN connector, `midi2` in the settings
+    Din,
+}
+
+impl MidiPort {
+    pub const ALL: [MidiPort; 2] = [MidiPort::Usb, MidiPort::Din];
+
+    fn other(&self) -> MidiPort {
+        match self {
+            MidiPort::Usb => MidiPort::Din,
+            MidiPort::Din => MidiPort::Usb,
+        }
+    }
+}
+
+impl fmt::Display for MidiPort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MidiPort::Usb => write!(f, "USB"),
+            MidiPort::Din => write!(f, "DIN"),
+        }
+    }
+}
+
+/// Where MIDI sent out of a port comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MidiSource {
+    /// Messages received on a port
+    Port(MidiPort),
+    /// The knob's own knob and key messages
+    Knob,
+}
+
+impl fmt::Display for MidiSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MidiSource::Port(port) => write!(f, "{}", port),
+            MidiSource::Knob => write!(f, "Knob"),
+        }
+    }
+}
+
+/// The MIDI routing of both ports, built on the per-port [`MidiSettings`].
+///
+/// For each port `in` is assumed to accept input, `out` to enable output,
+/// `thru` to echo input back out of the same port, `route` to forward input
+/// to the other port and `nano` to send the knob's own messages. The firmware
+/// doesn't document these flags, so the meanings are unverified.
+///
+/// ```
+/// use bongoknob::{MidiPort, MidiRouting, MidiSource};
+///
+/// let routing = MidiRouting::new()
+///     .connect(MidiSource::Knob, MidiPort::Usb)
+///     .connect(MidiSource::Port(MidiPort::Din), MidiPort::Usb);
+/// assert!(routing.forwards(MidiSource::Port(MidiPort::Din), MidiPort::Usb));
+/// println!("{}", routing);
+/// let patch = routing.settings_patch()?;
+/// # Ok::<(), bongoknob::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MidiRouting {
+    usb: MidiSettings,
+    din: MidiSettings,
+}
+
+impl MidiRouting {
+    /// No input, output or routing on either port.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The routing in `settings`, with ports that are missing turned off.
+    pub fn from_settings(settings: &Settings) -> Self {
+        MidiRouting {
+            usb: settings.midi_usb.clone().unwrap_or_default(),
+            din: settings.midi2.clone().unwrap_or_default(),
+        }
+    }
+
+    pub fn port(&self, port: MidiPort) -> &MidiSettings {
+        match port {
+            MidiPort::Usb => &self.usb,
+            MidiPort::Din => &self.din,
+        }
+    }
+
+    pub fn port_mut(&mut self, port: MidiPort) -> &mut MidiSettings {
+        match port {
+            MidiPort::Usb => &mut self.usb,
+            MidiPort::Din => &mut self.din,
+        }
+    }
+
+    /// Send MIDI from `from` out of `to`, enabling whatever that takes.
+    pub fn connect(mut self, from: MidiSource, to: MidiPort) -> Self {
+        match from {
+            MidiSource::Knob => self.port_mut(to).nano = true,
+            MidiSource::Port(port) => {
+                let settings = self.port_mut(port);
+                settings.input = true;
+                if port == to {
+                    settings.thru = true;
+                } else {
+                    settings.route = true;
+                }
+            }
+        }
+        self.port_mut(to).out = true;
+        self
+    }
+
+    /// Stop sending MIDI from `from` out of `to`. Input and output stay enabled.
+    pub fn disconnect(mut self, from: MidiSource, to: MidiPort) -> Self {
+        match from {
+            MidiSource::Knob => self.port_mut(to).nano = false,
+            MidiSource::Port(port) if port == to => self.port_mut(port).thru = false,
+            MidiSource::Port(port) => self.port_mut(port).route = false,
+        }
+        self
+    }
+
+    /// Whether MIDI from `from` actually leaves through `to`.
+    pub fn forwards(&self, from: MidiSource, to: MidiPort) -> bool {
+        let enabled = match from {
+            MidiSource::Knob => self.port(to).nano,
+            MidiSource::Port(port) => {
+                let settings = self.port(port);
+                let forwarded = if port == to {
+                    settings.thru
+                } else {
+                    settings.route
+                };
+                settings.input && forwarded
+            }
+        };
+        enabled && self.port(to).out
+    }
+
+    /// Every connection that carries MIDI.
+    pub fn routes(&self) -> Vec<(MidiSource, MidiPort)> {
+        let sources = [
+            MidiSource::Knob,
+            MidiSource::Port(MidiPort::Usb),
+            MidiSource::Port(MidiPort::Din),
+        ];
+        sources
+            .into_iter()
+            .flat_map(|from| MidiPort::ALL.map(|to| (from, to)))
+            .filter(|(from, to)| self.forwards(*from, *to))
+            .collect()
+    }
+
+    /// Check for flags that can't take effect, like routing input from a port
+    /// that doesn't accept any.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut problems = Vec::new();
+        for port in MidiPort::ALL {
+            let settings = self.port(port);
+            if !settings.input && (settings.thru || settings.route) {
+                problems.push(format!("{} forwards input but doesn't accept any", port));
+            }
+            if !settings.out && (settings.thru || settings.nano) {
+                problems.push(format!("{} sends MIDI but its output is off", port));
+            }
+            if settings.route && !self.port(port.other()).out {
+                problems.push(format!(
+                    "{} routes to {} but its output is off",
+                    port,
+                    port.other()
+                ));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidConfig(problems.join(", ")))
+        }
+    }
+
+    /// The settings to pass to `set_settings` to apply this routing.
+    pub fn settings_patch(&self) -> Result<Settings, Error> {
+        self.validate()?;
+        Ok(Settings {
+            midi_usb: Some(self.usb.clone()),
+            midi2: Some(self.din.clone()),
+            ..Default::default()
+        })
+    }
+}
+
+impl fmt::Display for MidiRouting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for port in MidiPort::ALL {
+            let settings = self.port(port);
+            let state = |enabled: bool| if enabled { "on" } else { "off" };
+            writeln!(
+                f,
+                "{}: input {}, output {}",
+                port,
+                state(settings.input),
+                state(settings.out)
+            )?;
+        }
+        let routes = self.routes();
+        if routes.is_empty() {
+            return write!(f, "No routes");
+        }
+        for (i, (from, to)) in routes.iter().enumerate() {
+            match from {
+                MidiSource::Port(port) if port == to => write!(f, "{} thru", port)?,
+                from => write!(f, "{} -> {}", from, to)?,
+            }
+            if i + 1 < routes.len() {
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connect() {
+        let routing = MidiRouting::new()
+            .connect(MidiSource::Knob, MidiPort::Usb)
+            .connect(MidiSource::Port(MidiPort::Usb), MidiPort::Din)
+            .connect(MidiSource::Port(MidiPort::Din), MidiPort::Din);
+        assert_eq!(
+            routing.routes(),
+            vec![
+                (MidiSource::Knob, MidiPort::Usb),
+                (MidiSource::Port(MidiPort::Usb), MidiPort::Din),
+                (MidiSource::Port(MidiPort::Din), MidiPort::Din),
+            ]
+        );
+        assert_eq!(
+            routing.to_string(),
+            "USB: input on, output on\nDIN: input on, output on\n\
+             Knob -> USB\nUSB -> DIN\nDIN thru"
+        );
+
+        let patch = routing
+            .disconnect(MidiSource::Port(MidiPort::Usb), MidiPort::Din)
+            .settings_patch()
+            .unwrap();
+        let json = serde_json::to_value(&patch).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "midiUsb": { "in": true, "out": true, "thru": false, "route": false, "nano": true },
+                "midi2": { "in": true, "out": true, "thru": true, "route": false, "nano": false },
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let settings = Settings {
+            midi_usb: Some(MidiSettings {
+                input: false,
+                out: false,
+                thru: false,
+                route: true,
+                nano: true,
+            }),
+            ..Default::default()
+        };
+        let routing = MidiRouting::from_settings(&settings);
+        assert!(routing.routes().is_empty());
+        assert_eq!(routing.to_string().lines().last(), Some("No routes"));
+        match routing.validate() {
+            Err(Error::InvalidConfig(problems)) => assert_eq!(
+                problems,
+                "USB forwards input but doesn't accept any, \
+                 USB sends MIDI but its output is off, \
+                 USB routes to DIN but its output is off"
+            ),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(routing.settings_patch().is_err());
+    }
+}
@@ -0,0 +1,434 @@
+use crate::error::Error;
+use crate::knob_device::KnobDevice;
+use crate::tracker::{PositionTracker, TrackerEvent};
+use crate::{Event, KeyEvent, Message, ScreenData};
+use crossbeam::channel::bounded;
+use crossbeam::select;
+use log::{error, info};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::thread;
+
+/// An argument of an [`OscMessage`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    String(String),
+}
+
+impl OscArg {
+    /// The argument as text, formatting numbers as needed.
+    pub fn to_text(&self) -> String {
+        match self {
+            OscArg::Int(i) => i.to_string(),
+            OscArg::Float(f) => f.to_string(),
+            OscArg::String(s) => s.clone(),
+        }
+    }
+
+    pub fn as_f32(&self) -> Option<f32> {
+        match self {
+            OscArg::Int(i) => Some(*i as f32),
+            OscArg::Float(f) => Some(*f),
+            OscArg::String(s) => s.parse().ok(),
+        }
+    }
+}
+
+/// A single OSC 1.0 message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+impl OscMessage {
+    pub fn new(address: impl Into<String>, args: Vec<OscArg>) -> Self {
+        OscMessage {
+            address: address.into(),
+            args,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_string(&mut out, &self.address);
+        let tags: String = std::iter::once(',')
+            .chain(self.args.iter().map(|arg| match arg {
+                OscArg::Int(_) => 'i',
+                OscArg::Float(_) => 'f',
+                OscArg::String(_) => 's',
+            }))
+            .collect();
+        write_string(&mut out, &tags);
+        for arg in &self.args {
+            match arg {
+                OscArg::Int(i) => out.extend_from_slice(&i.to_be_bytes()),
+                OscArg::Float(f) => out.extend_from_slice(&f.to_be_bytes()),
+                OscArg::String(s) => write_string(&mut out, s),
+            }
+        }
+        out
+    }
+
+    /// Decode a packet, flattening bundles into their messages.
+    pub fn decode(packet: &[u8]) -> Result<Vec<OscMessage>, Error> {
+        let mut reader = Reader { data: packet };
+        if packet.starts_with(b"#bundle\0") {
+            reader.take(16)?; // "#bundle" and the time tag
+            let mut messages = Vec::new();
+            while !reader.data.is_empty() {
+                let size = reader.int()? as usize;
+                messages.extend(Self::decode(reader.take(size)?)?);
+            }
+            return Ok(messages);
+        }
+
+        let address = reader.string()?;
+        if !address.starts_with('/') {
+            return Err(invalid("address must start with /"));
+        }
+        let mut args = Vec::new();
+        // a missing type tag string means no arguments
+        if !reader.data.is_empty() {
+            let tags = reader.string()?;
+            for tag in tags.strip_prefix(',').unwrap_or(&tags).chars() {
+                args.push(match tag {
+                    'i' => OscArg::Int(reader.int()?),
+                    'f' => OscArg::Float(f32::from_bits(reader.int()? as u32)),
+                    's' | 'S' => OscArg::String(reader.string()?),
+                    'T' => OscArg::Int(1),
+                    'F' => OscArg::Int(0),
+                    _ => return Err(invalid(&format!("unsupported OSC type tag `{}`", tag))),
+                });
+            }
+        }
+        Ok(vec![OscMessage { address, args }])
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::ConversionError(message.to_string())
+}
+
+/// Write a null terminated string padded to four bytes.
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(s.as_bytes());
+    let padding = 4 - s.len() % 4;
+    out.resize(out.len() + padding, 0);
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if n > self.data.len() {
+            return Err(invalid("truncated OSC packet"));
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn int(&mut self) -> Result<i32, Error> {
+        let bytes = self.take(4)?;
+        Ok(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let end = self
+            .data
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| invalid("unterminated OSC string"))?;
+        let s = String::from_utf8_lossy(&self.data[..end]).into_owned();
+        self.take((end / 4 + 1) * 4)?;
+        Ok(s)
+    }
+}
+
+/// Translates between a knob and OSC over UDP.
+///
+/// Sends `/knob/position i`, `/knob/delta i` and `/key/{id}/down` or
+/// `/key/{id}/up` (with `i 1` or `i 0`) to the target address. Accepts
+/// `/screen/title`, `/screen/data1` to `/screen/data4` with one argument and
+/// `/message title [text] [duration]` on the bound address.
+///
+/// ```no_run
+/// use bongoknob::OscBridge;
+///
+/// let device = bongoknob::connect(bongoknob::discover()?.remove(0))?;
+/// let bridge = OscBridge::bind("127.0.0.1:9000", "127.0.0.1:9001")?;
+/// bridge.run(&device)?;
+/// # Ok::<(), bongoknob::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct OscBridge {
+    socket: UdpSocket,
+    target: SocketAddr,
+}
+
+impl OscBridge {
+    /// Listen on `addr` and send events to `target`.
+    pub fn bind(addr: impl ToSocketAddrs, target: impl ToSocketAddrs) -> Result<Self, Error> {
+        let socket = UdpSocket::bind(addr)?;
+        let target = target
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| invalid("no target address"))?;
+        Ok(OscBridge { socket, target })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Bridge until the socket fails. Deltas follow the range of the first
+    /// knob of the current profile.
+    pub fn run<K: KnobDevice + Sync>(&self, knob: &K) -> Result<(), Error> {
+        info!(
+            "osc bridge on {}, sending to {}",
+            self.local_addr()?,
+            self.target
+        );
+        let profile = knob.get_current_profile()?;
+        let config = knob
+            .get_profile(&profile)
+            .ok()
+            .and_then(|p| p.knob)
+            .and_then(|k| k.into_iter().next());
+        let messages = knob.subscribe();
+        // dropped when receiving fails, so the event thread can be joined
+        let (stop_tx, stop) = bounded::<()>(0);
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let mut tracker = PositionTracker::new();
+                if let Some(config) = &config {
+                    tracker.set_knob(config);
+                }
+                loop {
+                    let message = select! {
+                        recv(messages) -> message => match message {
+                            Ok(message) => message,
+                            Err(_) => break,
+                        },
+                        recv(stop) -> _ => break,
+                    };
+                    for osc in translate_event(&mut tracker, &message) {
+                        if let Err(e) = self.socket.send_to(&osc.encode(), self.target) {
+                            error!("could not send OSC: {}", e);
+                        }
+                    }
+                }
+            });
+
+            let result = self.receive(knob);
+            drop(stop_tx);
+            result
+        })
+    }
+
+    /// Apply incoming messages until the socket fails.
+    fn receive(&self, knob: &impl KnobDevice) -> Result<(), Error> {
+        let mut buf = [0; 4096];
+        // a screen command sets every field, so keep the ones set before
+        let mut screen = ScreenData::default();
+        loop {
+            let (n, from) = self.socket.recv_from(&mut buf)?;
+            match OscMessage::decode(&buf[..n]) {
+                Ok(messages) => {
+                    for message in messages {
+                        if let Err(e) = apply(knob, &mut screen, &message) {
+                            error!("could not apply {} from {}: {}", message.address, from, e);
+                        }
+                    }
+                }
+                Err(e) => error!("bad OSC packet from {}: {}", from, e),
+            }
+        }
+    }
+}
+
+/// OSC messages for a message from the knob.
+fn translate_event(tracker: &mut PositionTracker, message: &Message) -> Vec<OscMessage> {
+    match message {
+        Message::Event(Event::Position(position)) => {
+            let mut out = vec![OscMessage::new(
+                "/knob/position",
+                vec![OscArg::Int(*position as i32)],
+            )];
+            if let Some(TrackerEvent::Rotated { delta, .. }) = tracker.handle(message) {
+                out.push(OscMessage::new(
+                    "/knob/delta",
+                    vec![OscArg::Int(delta as i32)],
+                ));
+            }
+            out
+        }
+        Message::Event(Event::Key(key)) => {
+            let (state, value) = match key {
+                KeyEvent::Down { .. } => ("down", 1),
+                KeyEvent::Up { .. } => ("up", 0),
+            };
+            vec![OscMessage::new(
+                format!("/key/{}/{}", key.id(), state),
+                vec![OscArg::Int(value)],
+            )]
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Act on an OSC message received from a client.
+fn apply(
+    knob: &impl KnobDevice,
+    screen: &mut ScreenData,
+    message: &OscMessage,
+) -> Result<(), Error> {
+    let text = |i: usize| message.args.get(i).map(OscArg::to_text);
+    let field = match message.address.as_str() {
+        "/screen/title" => &mut screen.title,
+        "/screen/data1" => &mut screen.data1,
+        "/screen/data2" => &mut screen.data2,
+        "/screen/data3" => &mut screen.data3,
+        "/screen/data4" => &mut screen.data4,
+        "/message" => {
+            let title = text(0).ok_or_else(|| invalid("/message needs a title"))?;
+            let duration = message.args.get(2).and_then(OscArg::as_f32);
+            return knob.set_message(Some(title), text(1), duration);
+        }
+        other => return Err(invalid(&format!("unknown address {}", other))),
+    };
+    *field = Some(text(0).unwrap_or_default());
+    knob.set_screen(screen.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::knob_device::fake::FakeKnob;
+    use crate::subscription::Envelope;
+    use crate::Command;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_encode_decode() {
+        let message = OscMessage::new(
+            "/message",
+            vec![
+                OscArg::String("Hi".to_string()),
+                OscArg::Int(-3),
+                OscArg::Float(1.5),
+            ],
+        );
+        let packet = message.encode();
+        assert_eq!(&packet[..12], b"/message\0\0\0\0");
+        assert_eq!(&packet[12..20], b",sif\0\0\0\0");
+        assert_eq!(packet.len() % 4, 0);
+        assert_eq!(OscMessage::decode(&packet).unwrap(), vec![message.clone()]);
+
+        let mut bundle = b"#bundle\0\0\0\0\0\0\0\0\x01".to_vec();
+        for _ in 0..2 {
+            bundle.extend_from_slice(&(packet.len() as i32).to_be_bytes());
+            bundle.extend_from_slice(&packet);
+        }
+        assert_eq!(OscMessage::decode(&bundle).unwrap().len(), 2);
+        assert!(OscMessage::decode(&packet[..10]).is_err());
+    }
+
+    /// A knob with the current profile `A`, wrapping around from 99 to 0.
+    fn knob() -> FakeKnob {
+        FakeKnob::default()
+            .reply(Command::GetProfiles, r#"{"profiles":["A"],"current":"A"}"#)
+            .reply(
+                Command::GetProfile("A".to_string()),
+                r#"{"profile":{"name":"A","knob":[{"valueMin":0,"valueMax":99,"angleMin":0,
+                "angleMax":255,"wrap":true,"step":1,"keyState":0,"haptic":{"mode":0,
+                "startPos":0,"endPos":255,"detentCount":0,"vernier":0,"kxForce":false,
+                "outputRamp":0,"detentStrength":0},"type":"cc","channel":1,"cc":1}]}}"#,
+            )
+    }
+
+    fn publish(knob: &FakeKnob, raw: &str) {
+        knob.bus.publish(&Envelope {
+            received_at: Instant::now(),
+            message: Message::try_from(raw).unwrap(),
+            raw: raw.to_string(),
+        });
+    }
+
+    #[test]
+    fn test_loopback() {
+        let knob = knob();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let bridge = OscBridge::bind("127.0.0.1:0", client.local_addr().unwrap()).unwrap();
+        let bridge_addr = bridge.local_addr().unwrap();
+        let running = knob.clone();
+        thread::spawn(move || bridge.run(&running));
+
+        let title = OscMessage::new("/screen/title", vec![OscArg::Float(0.5)]);
+        client.send_to(&title.encode(), bridge_addr).unwrap();
+
+        // the bridge subscribes on its own thread, so keep publishing
+        let mut received = Vec::new();
+        let mut buf = [0; 256];
+        for _ in 0..50 {
+            for raw in [r#"{"p":95}"#, r#"{"kd":2,"ks":4}"#] {
+                publish(&knob, raw);
+            }
+            while let Ok(n) = client.recv(&mut buf) {
+                received.extend(OscMessage::decode(&buf[..n]).unwrap());
+            }
+            if !received.is_empty() {
+                break;
+            }
+        }
+        assert_eq!(
+            received[..2],
+            [
+                OscMessage::new("/knob/position", vec![OscArg::Int(95)]),
+                OscMessage::new("/key/2/down", vec![OscArg::Int(1)]),
+            ]
+        );
+
+        // deltas wrap around the knob's range
+        publish(&knob, r#"{"p":5}"#);
+        let n = client.recv(&mut buf).unwrap();
+        assert_eq!(
+            OscMessage::decode(&buf[..n]).unwrap(),
+            [OscMessage::new("/knob/position", vec![OscArg::Int(5)])]
+        );
+        let n = client.recv(&mut buf).unwrap();
+        assert_eq!(
+            OscMessage::decode(&buf[..n]).unwrap(),
+            [OscMessage::new("/knob/delta", vec![OscArg::Int(10)])]
+        );
+
+        let commands = knob.commands.lock().unwrap();
+        assert!(matches!(
+            &commands[..],
+            [
+                Command::GetProfiles,
+                Command::GetProfile(_),
+                Command::SetScreen(ScreenData { title: Some(t), data1: None, .. }),
+            ] if t == "0.5"
+        ));
+    }
+
+    #[test]
+    fn test_socket_error() {
+        let knob = knob();
+        let bridge = OscBridge::bind("127.0.0.1:0", "127.0.0.1:9").unwrap();
+        // a receive timeout makes the socket fail
+        bridge
+            .socket
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        assert!(bridge.run(&knob).is_err());
+    }
+}
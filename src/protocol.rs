@@ -1,7 +1,8 @@
 use serde::de::{self, MapAccess, SeqAccess, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use serde_json::json;
+use serde_json::{json, Value};
 use std::fmt;
+use std::time::Duration;
 
 impl TryFrom<&str> for Message {
     type Error = crate::Error;
@@ -9,10 +10,7 @@ impl TryFrom<&str> for Message {
     fn try_from(value: &str) -> Result<Self, crate::Error> {
         match serde_json::from_str(value) {
             Ok(m) => Ok(m),
-            Err(e) => {
-                println!("Parse error: {:?}", e);
-                Err(crate::Error::ParseError(e))
-            }
+            Err(e) => Err(crate::Error::ParseError(e)),
         }
     }
 }
@@ -29,6 +27,21 @@ pub enum Message {
     Settings(SettingsRoot),
 }
 
+impl Message {
+    /// Name of the variant, e.g. `"Heartbeat"`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::Error(_) => "Error",
+            Message::Heartbeat(_) => "Heartbeat",
+            Message::Saved(_) => "Saved",
+            Message::Event(_) => "Event",
+            Message::Profiles(_) => "Profiles",
+            Message::Profile(_) => "Profile",
+            Message::Settings(_) => "Settings",
+        }
+    }
+}
+
 impl fmt::Display for Message {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -56,11 +69,40 @@ pub enum Command {
     ShowMessage(MessageDetails),
     SetScreen(ScreenData),
     SetSettings(Settings),
+    /// A line sent to the device as is
+    Raw(String),
+}
+
+impl Command {
+    /// Whether the device answers this command, as opposed to only reporting
+    /// errors. How `load` is answered isn't documented; it's assumed to reply
+    /// like `save` does.
+    pub(crate) fn expects_response(&self) -> bool {
+        match self {
+            Command::GetProfiles
+            | Command::GetProfile(_)
+            | Command::GetSettings
+            | Command::Save
+            | Command::Load => true,
+            Command::Raw(line) => {
+                let Ok(Value::Object(command)) = serde_json::from_str::<Value>(line) else {
+                    return false;
+                };
+                command.contains_key("profiles")
+                    || command.contains_key("profile")
+                    || command.get("settings").and_then(Value::as_str) == Some("?")
+                    || command.get("save") == Some(&Value::Bool(true))
+                    || command.get("load") == Some(&Value::Bool(true))
+            }
+            _ => false,
+        }
+    }
 }
 
 impl ToString for Command {
     fn to_string(&self) -> String {
         let val = match self {
+            Command::Raw(line) => return line.clone(),
             Command::GetProfiles => json!({
                 "profiles": "#all",
             }),
@@ -111,7 +153,14 @@ pub struct DeviceError {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Heartbeat {
-    idle: u64,
+    pub idle: u64,
+}
+
+impl Heartbeat {
+    /// Time since the knob was last touched, reported by the firmware in milliseconds.
+    pub fn idle_duration(&self) -> Duration {
+        Duration::from_millis(self.idle)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -155,7 +204,20 @@ impl fmt::Display for KeyEvent {
 }
 
 impl KeyEvent {
-    fn pretty_print(&self) -> String {
+    pub fn id(&self) -> u8 {
+        match self {
+            KeyEvent::Down { id, .. } | KeyEvent::Up { id, .. } => *id,
+        }
+    }
+
+    pub fn keys(&self) -> [bool; 4] {
+        match self {
+            KeyEvent::Down { keys, .. } | KeyEvent::Up { keys, .. } => *keys,
+        }
+    }
+
+    /// State of all four keys, e.g. `[XOOO]` while only the first is held.
+    pub fn pretty_print(&self) -> String {
         let (keys, _) = match self {
             KeyEvent::Down { keys, id } | KeyEvent::Up { keys, id } => (keys, id),
         };
@@ -261,82 +323,119 @@ pub struct ProfileRoot {
     pub profile: Profile,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct Profile {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub version: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub desc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub profile_tag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub led_enable: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub led_brightness: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub led_mode: Option<u8>,
     #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
         serialize_with = "serialize_color",
         deserialize_with = "deserialize_color"
     )]
     pub pointer: Option<Color>,
     #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
         serialize_with = "serialize_color",
         deserialize_with = "deserialize_color"
     )]
     pub primary: Option<Color>,
     #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
         serialize_with = "serialize_color",
         deserialize_with = "deserialize_color"
     )]
     pub secondary: Option<Color>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub attract_distance: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub feedback_strength: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub bounce_strength: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub haptic_click_strength: Option<u32>,
     #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
         serialize_with = "serialize_color",
         deserialize_with = "deserialize_color"
     )]
     pub button_a_idle: Option<Color>,
     #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
         serialize_with = "serialize_color",
         deserialize_with = "deserialize_color"
     )]
     pub button_b_idle: Option<Color>,
     #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
         serialize_with = "serialize_color",
         deserialize_with = "deserialize_color"
     )]
     pub button_c_idle: Option<Color>,
     #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
         serialize_with = "serialize_color",
         deserialize_with = "deserialize_color"
     )]
     pub button_d_idle: Option<Color>,
     #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
         serialize_with = "serialize_color",
         deserialize_with = "deserialize_color"
     )]
     pub button_a_press: Option<Color>,
     #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
         serialize_with = "serialize_color",
         deserialize_with = "deserialize_color"
     )]
     pub button_b_press: Option<Color>,
     #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
         serialize_with = "serialize_color",
         deserialize_with = "deserialize_color"
     )]
     pub button_c_press: Option<Color>,
     #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
         serialize_with = "serialize_color",
         deserialize_with = "deserialize_color"
     )]
     pub button_d_press: Option<Color>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub keys: Option<Vec<KeyDef>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub knob: Option<Vec<Knob>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub gui_enable: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub audio: Option<Audio>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -458,7 +557,7 @@ pub struct SetScreen {
     pub screen: ScreenData,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct ScreenData {
     pub title: Option<String>,
     pub data1: Option<String>,
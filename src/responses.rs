@@ -0,0 +1,177 @@
+use crate::error::Error;
+use crate::subscription::Envelope;
+use crate::Message;
+use crossbeam::channel::Sender;
+use log::error;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How long a command without a response can still be blamed for an error.
+const ERROR_GRACE: Duration = Duration::from_secs(1);
+
+/// Where the reply to a command goes.
+#[derive(Debug)]
+pub(crate) struct Reply {
+    /// Whether the device answers the command, rather than only reporting errors
+    pub answered: bool,
+    /// Receives the answer or error, if anyone is interested
+    pub to: Option<Sender<Envelope>>,
+}
+
+impl Reply {
+    /// Wait for the answer to a command.
+    pub fn response(to: Sender<Envelope>) -> Self {
+        Reply {
+            answered: true,
+            to: Some(to),
+        }
+    }
+
+    /// Don't wait for anything, but keep the command's place in line so it
+    /// can't take another command's reply.
+    pub fn ignore(command: &crate::Command) -> Self {
+        Reply {
+            answered: command.expects_response(),
+            to: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Pending {
+    id: u64,
+    sent_at: Instant,
+    reply: Reply,
+}
+
+/// Matches replies to the commands that caused them.
+///
+/// The device answers commands in the order it got them, so a reply belongs
+/// to the oldest command still waiting for one. Commands that are only
+/// answered when they fail are passed over by replies, and take errors that
+/// arrive within [`ERROR_GRACE`] of being sent.
+#[derive(Debug, Default)]
+pub(crate) struct ResponseQueue {
+    pending: VecDeque<Pending>,
+    next_id: u64,
+}
+
+impl ResponseQueue {
+    /// Record a command just sent, returning an id to [`cancel`](ResponseQueue::cancel) it.
+    pub fn push(&mut self, reply: Reply, sent_at: Instant) -> u64 {
+        self.expire(sent_at);
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending.push_back(Pending { id, sent_at, reply });
+        id
+    }
+
+    /// Forget a command that could not be sent after all.
+    pub fn cancel(&mut self, id: u64) {
+        self.pending.retain(|pending| pending.id != id);
+    }
+
+    /// Pass a reply on to whoever is waiting for it. Events and heartbeats
+    /// are left alone.
+    pub fn route(&mut self, envelope: Envelope) {
+        self.expire(envelope.received_at);
+        let pending = match &envelope.message {
+            Message::Event(_) | Message::Heartbeat(_) => return,
+            Message::Error(_) => self.pending.pop_front(),
+            _ => {
+                // commands that would have failed by now went through
+                while self.pending.front().is_some_and(|p| !p.reply.answered) {
+                    self.pending.pop_front();
+                }
+                self.pending.pop_front()
+            }
+        };
+
+        if let Some(to) = pending.and_then(|p| p.reply.to) {
+            let _ = to.send(envelope);
+        } else if let Message::Error(e) = envelope.message {
+            error!("device error: {}", Error::DeviceError(e.error, e.msg));
+        } else {
+            error!("unexpected response: {}", envelope.message);
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while self.pending.front().is_some_and(|p| {
+            !p.reply.answered && now.saturating_duration_since(p.sent_at) > ERROR_GRACE
+        }) {
+            self.pending.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Command;
+    use crossbeam::channel::unbounded;
+
+    fn envelope(raw: &str, received_at: Instant) -> Envelope {
+        Envelope {
+            received_at,
+            message: Message::try_from(raw).unwrap(),
+            raw: raw.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_route() {
+        let now = Instant::now();
+        let mut queue = ResponseQueue::default();
+        let (screen_tx, screen_rx) = unbounded();
+        let (save_tx, save_rx) = unbounded();
+        let (profile_tx, profile_rx) = unbounded();
+        queue.push(
+            Reply {
+                answered: false,
+                to: Some(screen_tx),
+            },
+            now,
+        );
+        queue.push(Reply::response(save_tx), now);
+        queue.push(Reply::ignore(&Command::Recalibrate), now);
+        queue.push(Reply::response(profile_tx), now);
+
+        queue.route(envelope(r#"{"p":3}"#, now));
+        queue.route(envelope(r#"{"saved":true}"#, now));
+        queue.route(envelope(r#"{"error":"busy","msg":null}"#, now));
+        queue.route(envelope(r#"{"error":"not found","msg":null}"#, now));
+
+        assert!(screen_rx.try_recv().is_err());
+        assert_eq!(save_rx.try_recv().unwrap().raw, r#"{"saved":true}"#);
+        assert_eq!(
+            profile_rx.try_recv().unwrap().raw,
+            r#"{"error":"not found","msg":null}"#
+        );
+    }
+
+    #[test]
+    fn test_expire() {
+        let now = Instant::now();
+        let mut queue = ResponseQueue::default();
+        let (screen_tx, screen_rx) = unbounded();
+        let (profile_tx, profile_rx) = unbounded();
+        queue.push(
+            Reply {
+                answered: false,
+                to: Some(screen_tx),
+            },
+            now,
+        );
+        let id = queue.push(Reply::ignore(&Command::Save), now);
+        queue.cancel(id);
+        queue.push(Reply::response(profile_tx), now + ERROR_GRACE * 2);
+
+        queue.route(envelope(
+            r#"{"error":"not found","msg":null}"#,
+            now + ERROR_GRACE * 2,
+        ));
+        assert!(screen_rx.try_recv().is_err());
+        assert!(profile_rx.try_recv().is_ok());
+    }
+}
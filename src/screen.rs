@@ -0,0 +1,290 @@
+use crate::error::Error;
+use crate::knob_device::KnobDevice;
+use crate::{Command, Event, KeyEvent, Message, MessageDetails, ScreenData};
+use std::time::{Duration, Instant};
+
+/// A temporary message shown on top of the current page.
+#[derive(Debug, Clone)]
+pub struct Toast {
+    pub title: Option<String>,
+    pub text: Option<String>,
+    pub duration: Duration,
+    /// Higher priorities are shown first. Equal priorities are shown in order.
+    pub priority: u8,
+}
+
+impl Toast {
+    pub fn new(title: impl Into<String>, duration: Duration) -> Self {
+        Toast {
+            title: Some(title.into()),
+            text: None,
+            duration,
+            priority: 0,
+        }
+    }
+
+    pub fn text(mut self, text: impl Into<String>) -> Self {
+        self.text = Some(text.into());
+        self
+    }
+
+    pub fn priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+}
+
+/// Shares the device screen between named pages and toast notifications.
+///
+/// One page is visible at a time and can be cycled with a key. Toasts are
+/// queued by priority and shown one after another, after which the current
+/// page is restored. Nothing is sent while a toast is up, so page updates
+/// never clobber it.
+///
+/// ```no_run
+/// use bongoknob::{ScreenData, ScreenManager, Toast};
+/// use std::time::Duration;
+///
+/// # let device: bongoknob::Device = unimplemented!();
+/// let mut screen = ScreenManager::new();
+/// screen.cycle_with_key(3);
+/// screen.set_page("mixer", ScreenData { title: Some("Mixer".into()), ..Default::default() });
+/// screen.set_page("fx", ScreenData { title: Some("FX".into()), ..Default::default() });
+/// screen.toast(Toast::new("Saved", Duration::from_secs(2)).priority(1));
+///
+/// let messages = device.subscribe();
+/// loop {
+///     if let Ok(message) = messages.recv_timeout(Duration::from_millis(50)) {
+///         screen.handle(&message);
+///     }
+///     screen.update(&device)?;
+/// }
+/// # Ok::<(), bongoknob::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ScreenManager {
+    pages: Vec<(String, ScreenData)>,
+    current: usize,
+    cycle_key: Option<u8>,
+    toasts: Vec<(u64, Toast)>,
+    sequence: u64,
+    showing_until: Option<Instant>,
+    dirty: bool,
+}
+
+impl ScreenManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a page, or replace the contents of an existing one.
+    pub fn set_page(&mut self, name: &str, data: ScreenData) {
+        match self.pages.iter().position(|(n, _)| n == name) {
+            Some(index) => {
+                self.pages[index].1 = data;
+                if index == self.current {
+                    self.dirty = true;
+                }
+            }
+            None => {
+                self.pages.push((name.to_string(), data));
+                if self.pages.len() == 1 {
+                    self.dirty = true;
+                }
+            }
+        }
+    }
+
+    pub fn remove_page(&mut self, name: &str) -> Option<ScreenData> {
+        let index = self.pages.iter().position(|(n, _)| n == name)?;
+        let (_, data) = self.pages.remove(index);
+        if index == self.current {
+            self.dirty = true;
+        }
+        if index < self.current || self.current >= self.pages.len() {
+            self.current = self.current.saturating_sub(1);
+        }
+        Some(data)
+    }
+
+    pub fn page(&self, name: &str) -> Option<&ScreenData> {
+        self.pages.iter().find(|(n, _)| n == name).map(|(_, d)| d)
+    }
+
+    pub fn current_page(&self) -> Option<&str> {
+        self.pages.get(self.current).map(|(n, _)| n.as_str())
+    }
+
+    /// Switch to the named page. Returns false if there is no such page.
+    pub fn show_page(&mut self, name: &str) -> bool {
+        match self.pages.iter().position(|(n, _)| n == name) {
+            Some(index) => {
+                self.switch(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn next_page(&mut self) {
+        if !self.pages.is_empty() {
+            self.switch((self.current + 1) % self.pages.len());
+        }
+    }
+
+    pub fn previous_page(&mut self) {
+        if !self.pages.is_empty() {
+            self.switch((self.current + self.pages.len() - 1) % self.pages.len());
+        }
+    }
+
+    /// Cycle to the next page whenever this key is pressed, see [`handle`](ScreenManager::handle).
+    pub fn cycle_with_key(&mut self, id: u8) {
+        self.cycle_key = Some(id);
+    }
+
+    pub fn toast(&mut self, toast: Toast) {
+        self.toasts.push((self.sequence, toast));
+        self.sequence += 1;
+    }
+
+    /// Whether a toast is on screen or waiting to be shown.
+    pub fn has_toasts(&self) -> bool {
+        self.showing_until.is_some() || !self.toasts.is_empty()
+    }
+
+    /// Process a message from the device, cycling pages on the configured key.
+    pub fn handle(&mut self, message: &Message) {
+        if let Message::Event(Event::Key(KeyEvent::Down { id, .. })) = message {
+            if Some(*id) == self.cycle_key {
+                self.next_page();
+            }
+        }
+    }
+
+    /// Commands needed to bring the screen up to date at `now`.
+    pub fn poll(&mut self, now: Instant) -> Vec<Command> {
+        if let Some(until) = self.showing_until {
+            if now < until {
+                return Vec::new();
+            }
+            self.showing_until = None;
+            self.dirty = true;
+        }
+
+        if let Some(toast) = self.next_toast() {
+            self.showing_until = Some(now + toast.duration);
+            return vec![Command::ShowMessage(MessageDetails {
+                title: toast.title,
+                text: toast.text,
+                duration: Some(toast.duration.as_secs_f32()),
+            })];
+        }
+
+        if self.dirty {
+            self.dirty = false;
+            if let Some((_, data)) = self.pages.get(self.current) {
+                return vec![Command::SetScreen(data.clone())];
+            }
+        }
+
+        Vec::new()
+    }
+
+    /// Send whatever is due to the device.
+    pub fn update(&mut self, device: &impl KnobDevice) -> Result<(), Error> {
+        for command in self.poll(Instant::now()) {
+            device.command(command)?;
+        }
+        Ok(())
+    }
+
+    fn switch(&mut self, index: usize) {
+        if index != self.current {
+            self.current = index;
+            self.dirty = true;
+        }
+    }
+
+    fn next_toast(&mut self) -> Option<Toast> {
+        let index = self
+            .toasts
+            .iter()
+            .enumerate()
+            .max_by(|(_, (a_seq, a)), (_, (b_seq, b))| {
+                a.priority.cmp(&b.priority).then(b_seq.cmp(a_seq))
+            })
+            .map(|(index, _)| index)?;
+        Some(self.toasts.remove(index).1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(title: &str) -> ScreenData {
+        ScreenData {
+            title: Some(title.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn title(commands: &[Command]) -> Option<&str> {
+        match commands {
+            [Command::SetScreen(data)] => data.title.as_deref(),
+            [Command::ShowMessage(msg)] => msg.title.as_deref(),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_pages() {
+        let mut screen = ScreenManager::new();
+        let now = Instant::now();
+        screen.cycle_with_key(2);
+        screen.set_page("a", page("A"));
+        screen.set_page("b", page("B"));
+
+        assert_eq!(title(&screen.poll(now)), Some("A"));
+        assert!(screen.poll(now).is_empty());
+
+        screen.handle(&Message::Event(Event::Key(KeyEvent::Down {
+            keys: [false, false, true, false],
+            id: 2,
+        })));
+        assert_eq!(screen.current_page(), Some("b"));
+        assert_eq!(title(&screen.poll(now)), Some("B"));
+
+        // updating a page that isn't visible sends nothing
+        screen.set_page("a", page("A2"));
+        assert!(screen.poll(now).is_empty());
+        screen.next_page();
+        assert_eq!(title(&screen.poll(now)), Some("A2"));
+    }
+
+    #[test]
+    fn test_toasts() {
+        let mut screen = ScreenManager::new();
+        let now = Instant::now();
+        screen.set_page("a", page("A"));
+        screen.poll(now);
+
+        screen.toast(Toast::new("low", Duration::from_secs(1)));
+        screen.toast(Toast::new("high", Duration::from_secs(1)).priority(5));
+        screen.toast(Toast::new("low 2", Duration::from_secs(1)));
+
+        assert_eq!(title(&screen.poll(now)), Some("high"));
+        // page changes wait for the toast
+        screen.set_page("a", page("A2"));
+        assert!(screen.poll(now + Duration::from_millis(500)).is_empty());
+
+        let later = now + Duration::from_secs(1);
+        assert_eq!(title(&screen.poll(later)), Some("low"));
+        let later = later + Duration::from_secs(1);
+        assert_eq!(title(&screen.poll(later)), Some("low 2"));
+        let later = later + Duration::from_secs(1);
+        assert_eq!(title(&screen.poll(later)), Some("A2"));
+        assert!(!screen.has_toasts());
+    }
+}
@@ -0,0 +1,273 @@
+use crate::error::Error;
+use crate::knob_device::KnobDevice;
+use crate::{Command, ScreenData};
+use serde_json::{json, Map, Value};
+use std::time::{Duration, Instant};
+
+const FIELDS: usize = 5;
+/// Keys of the fields in a screen command
+const FIELD_NAMES: [&str; FIELDS] = ["title", "data1", "data2", "data3", "data4"];
+const SCROLL_GAP: &str = "   ";
+
+/// How text wider than the display is handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Cut the text at the display width
+    Truncate,
+    /// Scroll the text one character per `step`. Only advances when the
+    /// updater is polled, so keep calling `update` while text is scrolling.
+    Scroll { step: Duration },
+}
+
+/// Characters that fit on the display, per field. These depend on the
+/// display and font and aren't reported by the device, so measure them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScreenLimits {
+    pub title: usize,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Field {
+    text: Option<String>,
+    changed_at: Option<Instant>,
+    sent: Option<String>,
+}
+
+/// Keeps the screen in sync with as few `set_screen` calls as possible.
+///
+/// Updates are merged into the desired screen state and sent at most once per
+/// frame, and only the fields that changed since the last send go over the
+/// wire, relying on the firmware to keep fields a screen command leaves out.
+/// Text is fitted to the [`ScreenLimits`] so the firmware never has to clip
+/// it.
+///
+/// ```no_run
+/// use bongoknob::{ScreenLimits, ScreenUpdater};
+/// use std::time::Duration;
+///
+/// # let device: bongoknob::Device = unimplemented!();
+/// let limits = ScreenLimits { title: 12, line: 20 };
+/// let mut screen = ScreenUpdater::new(Duration::from_millis(50), limits);
+/// for volume in 0..100 {
+///     screen.set_line(1, format!("Volume {}", volume));
+///     screen.update(&device)?;
+/// }
+/// # Ok::<(), bongoknob::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ScreenUpdater {
+    frame: Duration,
+    limits: ScreenLimits,
+    overflow: Overflow,
+    fields: [Field; FIELDS],
+    last_send: Option<Instant>,
+}
+
+impl ScreenUpdater {
+    /// Send at most one update per `frame`, fitting text to `limits`.
+    pub fn new(frame: Duration, limits: ScreenLimits) -> Self {
+        ScreenUpdater {
+            frame,
+            limits,
+            overflow: Overflow::Truncate,
+            fields: Default::default(),
+            last_send: None,
+        }
+    }
+
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Merge the fields that are set into the desired screen state.
+    pub fn set(&mut self, data: ScreenData) {
+        let now = Instant::now();
+        let values = [data.title, data.data1, data.data2, data.data3, data.data4];
+        for (field, value) in self.fields.iter_mut().zip(values) {
+            if let Some(value) = value {
+                if field.text.as_ref() != Some(&value) {
+                    field.text = Some(value);
+                    field.changed_at = Some(now);
+                }
+            }
+        }
+    }
+
+    pub fn set_title(&mut self, title: impl Into<String>) {
+        self.set(ScreenData {
+            title: Some(title.into()),
+            ..Default::default()
+        });
+    }
+
+    /// Set one of the four data lines, numbered 1 to 4 like the `data` fields.
+    pub fn set_line(&mut self, line: usize, text: impl Into<String>) {
+        let text = Some(text.into());
+        let data = match line {
+            1 => ScreenData {
+                data1: text,
+                ..Default::default()
+            },
+            2 => ScreenData {
+                data2: text,
+                ..Default::default()
+            },
+            3 => ScreenData {
+                data3: text,
+                ..Default::default()
+            },
+            4 => ScreenData {
+                data4: text,
+                ..Default::default()
+            },
+            _ => return,
+        };
+        self.set(data);
+    }
+
+    /// Forget what was sent so the next update resends every field, e.g.
+    /// after something else drew on the screen.
+    pub fn invalidate(&mut self) {
+        for field in self.fields.iter_mut() {
+            field.sent = None;
+        }
+    }
+
+    /// The update that is due at `now`, if any. It holds only the changed
+    /// fields, so it is sent as a raw screen command rather than
+    /// [`Command::SetScreen`], which sends every field.
+    pub fn poll(&mut self, now: Instant) -> Option<Command> {
+        if let Some(last) = self.last_send {
+            if now < last + self.frame {
+                return None;
+            }
+        }
+
+        let mut changed = Map::new();
+        for (index, field) in self.fields.iter_mut().enumerate() {
+            let Some(text) = &field.text else {
+                continue;
+            };
+            let width = if index == 0 {
+                self.limits.title
+            } else {
+                self.limits.line
+            };
+            let since = field.changed_at.unwrap_or(now);
+            let rendered = fit(
+                text,
+                width,
+                self.overflow,
+                now.saturating_duration_since(since),
+            );
+            if field.sent.as_ref() != Some(&rendered) {
+                field.sent = Some(rendered.clone());
+                changed.insert(FIELD_NAMES[index].to_string(), Value::String(rendered));
+            }
+        }
+
+        if changed.is_empty() {
+            return None;
+        }
+        self.last_send = Some(now);
+        Some(Command::Raw(json!({ "screen": changed }).to_string()))
+    }
+
+    /// Send the pending update to the device, if one is due.
+    pub fn update(&mut self, device: &impl KnobDevice) -> Result<(), Error> {
+        match self.poll(Instant::now()) {
+            Some(command) => device.command(command),
+            None => Ok(()),
+        }
+    }
+}
+
+fn fit(text: &str, width: usize, overflow: Overflow, elapsed: Duration) -> String {
+    let len = text.chars().count();
+    if len <= width {
+        return text.to_string();
+    }
+
+    match overflow {
+        Overflow::Truncate => text.chars().take(width).collect(),
+        Overflow::Scroll { step } => {
+            let cycle = len + SCROLL_GAP.len();
+            let offset = match step.as_nanos() {
+                0 => 0,
+                step => (elapsed.as_nanos() / step) as usize % cycle,
+            };
+            text.chars()
+                .chain(SCROLL_GAP.chars())
+                .cycle()
+                .skip(offset)
+                .take(width)
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(command: Option<Command>) -> Value {
+        match command {
+            Some(Command::Raw(line)) => {
+                serde_json::from_str::<Value>(&line).unwrap()["screen"].take()
+            }
+            c => panic!("unexpected command {:?}", c),
+        }
+    }
+
+    #[test]
+    fn test_only_changed_fields_per_frame() {
+        let limits = ScreenLimits {
+            title: 12,
+            line: 20,
+        };
+        let mut screen = ScreenUpdater::new(Duration::from_millis(50), limits);
+        let now = Instant::now();
+
+        screen.set_title("Mixer");
+        screen.set_line(1, "Vol 1");
+        assert_eq!(
+            fields(screen.poll(now)),
+            json!({ "title": "Mixer", "data1": "Vol 1" })
+        );
+
+        // coalesced within the frame, only the last value is sent
+        screen.set_line(1, "Vol 2");
+        assert!(screen.poll(now + Duration::from_millis(10)).is_none());
+        screen.set_line(1, "Vol 3");
+        screen.set_title("Mixer");
+        assert_eq!(
+            fields(screen.poll(now + Duration::from_millis(50))),
+            json!({ "data1": "Vol 3" })
+        );
+        assert!(screen.poll(now + Duration::from_millis(200)).is_none());
+
+        screen.invalidate();
+        assert!(screen.poll(now + Duration::from_millis(250)).is_some());
+
+        // a screen command on its own sends every field
+        let line = Command::SetScreen(ScreenData::default()).to_string();
+        assert!(line.contains(r#""data4":null"#));
+    }
+
+    #[test]
+    fn test_fit() {
+        let long = "abcdefghij";
+        assert_eq!(fit(long, 20, Overflow::Truncate, Duration::ZERO), long);
+        assert_eq!(fit(long, 4, Overflow::Truncate, Duration::ZERO), "abcd");
+
+        let scroll = Overflow::Scroll {
+            step: Duration::from_millis(100),
+        };
+        assert_eq!(fit(long, 4, scroll, Duration::ZERO), "abcd");
+        assert_eq!(fit(long, 4, scroll, Duration::from_millis(250)), "cdef");
+        assert_eq!(fit(long, 4, scroll, Duration::from_millis(900)), "j   ");
+        assert_eq!(fit(long, 4, scroll, Duration::from_millis(1300)), "abcd");
+    }
+}
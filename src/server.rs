@@ -0,0 +1,501 @@
+use crate::error::Error;
+use crate::knob_device::KnobDevice;
+use crate::subscription::Subscription;
+use crate::{Command, Message, MessageDetails};
+use crossbeam::channel::{bounded, RecvTimeoutError};
+use log::{debug, error, info};
+use serde::Serialize;
+use serde_json::json;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tungstenite::protocol::Role;
+use tungstenite::WebSocket;
+
+type Knob = Arc<dyn KnobDevice + Send + Sync>;
+
+/// Threads answering requests; further connections wait for a free one.
+const WORKERS: usize = 4;
+/// WebSocket streams open at once, each on a thread of its own.
+const MAX_STREAMS: usize = 16;
+/// How long a client gets to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_HEAD: usize = 8 * 1024;
+const MAX_BODY: usize = 64 * 1024;
+
+/// Serves a knob to browsers over HTTP.
+///
+/// | Route | |
+/// |---|---|
+/// | `GET /settings` | the device settings |
+/// | `GET /profiles` | profile names and the current profile |
+/// | `GET /profiles/{name}` | one profile |
+/// | `PUT /profiles/{name}` | 501, the firmware has no known command to write a profile |
+/// | `POST /message` | show a message, body `{"title", "text", "duration"}` |
+/// | `GET /ws` | WebSocket sending events and heartbeats as [`Message`] JSON |
+///
+/// Errors are answered as `{"error", "msg"}`, with 502 for errors the device
+/// reports. Every connection carries a single request.
+///
+/// ```no_run
+/// let device = bongoknob::connect(bongoknob::discover()?.remove(0))?;
+/// let server = bongoknob::Server::bind(device, "127.0.0.1:8080")?;
+/// server.run()?;
+/// # Ok::<(), bongoknob::Error>(())
+/// ```
+pub struct Server {
+    knob: Knob,
+    listener: TcpListener,
+    streams: Arc<AtomicUsize>,
+}
+
+impl std::fmt::Debug for Server {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Server")
+            .field("addr", &self.local_addr())
+            .field("streams", &self.streams.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+impl Server {
+    pub fn bind(
+        knob: impl KnobDevice + Send + Sync + 'static,
+        addr: impl ToSocketAddrs,
+    ) -> Result<Self, Error> {
+        Ok(Server {
+            knob: Arc::new(knob),
+            listener: TcpListener::bind(addr)?,
+            streams: Arc::default(),
+        })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.local_addr().ok()
+    }
+
+    /// Serve requests on a fixed pool of worker threads.
+    pub fn run(&self) -> Result<(), Error> {
+        info!("serving http on {:?}", self.local_addr());
+        thread::scope(|scope| {
+            let (connections, incoming) = bounded::<TcpStream>(0);
+            for _ in 0..WORKERS {
+                let incoming = incoming.clone();
+                scope.spawn(move || incoming.iter().for_each(|stream| self.serve(stream)));
+            }
+            for stream in self.listener.incoming() {
+                match stream {
+                    Ok(stream) => connections.send(stream).expect("workers are running"),
+                    Err(e) => error!("could not accept connection: {}", e),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Serve requests on a background thread.
+    pub fn spawn(self) -> JoinHandle<Result<(), Error>> {
+        thread::spawn(move || self.run())
+    }
+
+    fn serve(&self, mut stream: TcpStream) {
+        // a slow client only holds up its worker for so long
+        let request = match stream
+            .set_read_timeout(Some(REQUEST_TIMEOUT))
+            .and_then(|_| read_request(&mut stream))
+        {
+            Ok(Some(request)) => request,
+            Ok(None) => return respond(&mut stream, error_response(400, "invalid request", None)),
+            Err(e) => {
+                debug!("could not read request: {}", e);
+                return;
+            }
+        };
+        debug!("{} {}", request.method, request.path);
+
+        let segments: Option<Vec<String>> = request
+            .path
+            .split('?')
+            .next()
+            .unwrap_or_default()
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(percent_decode)
+            .collect();
+        let Some(segments) = segments else {
+            return respond(&mut stream, error_response(400, "invalid path", None));
+        };
+        let path: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+        if let ("GET", ["ws"]) = (request.method.as_str(), path.as_slice()) {
+            return self.upgrade(stream, request.websocket_key);
+        }
+        let response = handle(&self.knob, &request, &path);
+        respond(&mut stream, response);
+    }
+
+    /// Answer a WebSocket upgrade and stream messages on a thread of its own.
+    fn upgrade(&self, mut stream: TcpStream, key: Option<String>) {
+        let Some(key) = key else {
+            return respond(
+                &mut stream,
+                error_response(400, "expected a websocket upgrade", None),
+            );
+        };
+        if self.streams.fetch_add(1, Ordering::SeqCst) >= MAX_STREAMS {
+            self.streams.fetch_sub(1, Ordering::SeqCst);
+            return respond(&mut stream, error_response(503, "too many streams", None));
+        }
+
+        let messages = self.knob.subscribe();
+        let head = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            tungstenite::handshake::derive_accept_key(key.as_bytes())
+        );
+        let streams = self.streams.clone();
+        match stream.write_all(head.as_bytes()) {
+            Ok(_) => {
+                thread::spawn(move || {
+                    send_messages(stream, messages);
+                    streams.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+            Err(e) => {
+                debug!("could not upgrade: {}", e);
+                streams.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    websocket_key: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Read a request, or `None` if it isn't valid HTTP or is too large.
+fn read_request(stream: &mut TcpStream) -> io::Result<Option<Request>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+    let (mut request, length) = loop {
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+
+        let mut headers = [httparse::EMPTY_HEADER; 32];
+        let mut parsed = httparse::Request::new(&mut headers);
+        match parsed.parse(&buf) {
+            Ok(httparse::Status::Complete(len)) => {
+                let header = |name: &str| {
+                    parsed
+                        .headers
+                        .iter()
+                        .find(|h| h.name.eq_ignore_ascii_case(name))
+                        .and_then(|h| std::str::from_utf8(h.value).ok())
+                };
+                let Ok(length) = header("Content-Length").map_or(Ok(0), |l| l.trim().parse())
+                else {
+                    return Ok(None);
+                };
+                let request = Request {
+                    method: parsed.method.unwrap_or_default().to_string(),
+                    path: parsed.path.unwrap_or_default().to_string(),
+                    websocket_key: header("Sec-WebSocket-Key").map(str::to_string),
+                    body: buf[len..].to_vec(),
+                };
+                break (request, length);
+            }
+            Ok(httparse::Status::Partial) if buf.len() < MAX_HEAD => {}
+            _ => return Ok(None),
+        }
+    };
+
+    if length > MAX_BODY {
+        return Ok(None);
+    }
+    request.body.truncate(length);
+    let missing = (length - request.body.len()) as u64;
+    stream.take(missing).read_to_end(&mut request.body)?;
+    if request.body.len() < length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(Some(request))
+}
+
+fn handle(knob: &Knob, request: &Request, path: &[&str]) -> Response {
+    let result = match (request.method.as_str(), path) {
+        ("GET", ["settings"]) => knob.get_settings().map(|s| json_response(&s)),
+        ("GET", ["profiles"]) => match knob.command_response(Command::GetProfiles) {
+            Ok(Message::Profiles(profiles)) => Ok(json_response(&profiles)),
+            Ok(message) => Err(Error::UnexpectedResponse(message)),
+            Err(e) => Err(e),
+        },
+        ("GET", ["profiles", name]) => knob.get_profile(name).map(|p| json_response(&p)),
+        ("PUT", ["profiles", _]) => Ok(error_response(
+            501,
+            "updating profiles is not supported",
+            Some("the firmware has no known command to write a profile".to_string()),
+        )),
+        ("POST", ["message"]) => serde_json::from_slice::<MessageDetails>(&request.body)
+            .map_err(Error::from)
+            .and_then(|m| knob.set_message(m.title, m.text, m.duration))
+            .map(|_| empty()),
+        (_, ["ws" | "settings" | "profiles" | "message"]) | (_, ["profiles", _]) => {
+            Ok(error_response(405, "method not allowed", None))
+        }
+        _ => Ok(error_response(404, "not found", None)),
+    };
+
+    result.unwrap_or_else(|e| match e {
+        Error::CommandError(error, msg) => error_response(502, &error, msg),
+        Error::ParseError(e) => error_response(400, "invalid body", Some(e.to_string())),
+        e => error_response(500, &e.to_string(), None),
+    })
+}
+
+/// Send events and heartbeats until the browser closes the stream or goes
+/// away. Pings are answered and a close is confirmed while waiting.
+fn send_messages(stream: TcpStream, messages: Subscription<Message>) {
+    // reads only wait briefly, so they can be interleaved with sending
+    if let Err(e) = stream.set_read_timeout(Some(Duration::from_millis(1))) {
+        error!("could not set up stream: {}", e);
+        return;
+    }
+    let mut ws = WebSocket::from_raw_socket(stream, Role::Server, None);
+    loop {
+        match messages.recv_timeout(Duration::from_millis(50)) {
+            Ok(message) => {
+                let Ok(json) = serde_json::to_string(&message) else {
+                    continue;
+                };
+                if ws.send(tungstenite::Message::Text(json)).is_err() {
+                    return;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                let _ = ws.close(None).and_then(|_| ws.flush());
+                return;
+            }
+        }
+        match ws.read() {
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            // closed by the browser, or broken
+            Err(_) => return,
+        }
+    }
+}
+
+/// Decode `%XX` escapes in a path segment, e.g. a profile name with spaces.
+fn percent_decode(segment: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut rest = segment.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+struct Response {
+    status: u16,
+    /// JSON body
+    body: Vec<u8>,
+}
+
+fn respond(stream: &mut TcpStream, response: Response) {
+    let reason = match response.status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        response.status,
+        reason,
+        response.body.len()
+    );
+    if !response.body.is_empty() {
+        head.push_str("Content-Type: application/json\r\n");
+    }
+    head.push_str("\r\n");
+    let written = stream
+        .write_all(head.as_bytes())
+        .and_then(|_| stream.write_all(&response.body));
+    if let Err(e) = written {
+        error!("could not respond: {}", e);
+    }
+}
+
+fn json_response(value: &impl Serialize) -> Response {
+    Response {
+        status: 200,
+        body: serde_json::to_vec(value).unwrap_or_default(),
+    }
+}
+
+fn error_response(status: u16, error: &str, msg: Option<String>) -> Response {
+    Response {
+        status,
+        ..json_response(&json!({ "error": error, "msg": msg }))
+    }
+}
+
+fn empty() -> Response {
+    Response {
+        status: 204,
+        body: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::knob_device::fake::FakeKnob;
+    use crate::subscription::Envelope;
+    use std::time::Instant;
+
+    /// Send a request and return the status and body.
+    fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: test\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split("\r\n\r\n").nth(1).unwrap_or_default();
+        (status, body.to_string())
+    }
+
+    #[test]
+    fn test_routes() {
+        let knob = FakeKnob::default().reply(
+            Command::GetProfiles,
+            r#"{"profiles":["A","B"],"current":"B"}"#,
+        );
+        let server = Server::bind(knob.clone(), "127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        server.spawn();
+
+        assert_eq!(
+            request(addr, "GET", "/profiles", ""),
+            (200, r#"{"profiles":["A","B"],"current":"B"}"#.to_string())
+        );
+        let (status, body) = request(addr, "GET", "/profiles/C", "");
+        assert_eq!(status, 502);
+        assert!(body.contains("not found"));
+        assert_eq!(request(addr, "GET", "/profiles/My%20Synth", "").0, 502);
+        assert_eq!(
+            request(addr, "PUT", "/profiles/A", r#"{"name":"A"}"#).0,
+            501
+        );
+        assert_eq!(request(addr, "GET", "/profiles/%zz", "").0, 400);
+        assert_eq!(
+            request(addr, "POST", "/message", r#"{"title":"Hi"}"#).0,
+            204
+        );
+        assert_eq!(request(addr, "POST", "/message", "{").0, 400);
+        assert_eq!(request(addr, "DELETE", "/message", "").0, 405);
+        assert_eq!(request(addr, "GET", "/nothing", "").0, 404);
+
+        let commands = knob.commands.lock().unwrap();
+        assert!(matches!(
+            &commands[..],
+            [
+                Command::GetProfiles,
+                Command::GetProfile(c),
+                Command::GetProfile(synth),
+                Command::ShowMessage(m),
+            ] if c == "C" && synth == "My Synth" && m.title.as_deref() == Some("Hi")
+        ));
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("My%20Synth").as_deref(), Some("My Synth"));
+        assert_eq!(percent_decode("Str%C3%B6m").as_deref(), Some("Ström"));
+        assert_eq!(percent_decode("100%"), None);
+        assert_eq!(percent_decode("%FF"), None);
+    }
+
+    #[test]
+    fn test_websocket() {
+        let knob = FakeKnob::default();
+        let server = Server::bind(knob.clone(), "127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let streams = server.streams.clone();
+        server.spawn();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "GET /ws HTTP/1.1\r\nHost: test\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n"
+        )
+        .unwrap();
+        let mut head = Vec::new();
+        let mut byte = [0];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        assert!(head.starts_with(b"HTTP/1.1 101"));
+        assert_eq!(streams.load(Ordering::SeqCst), 1);
+
+        // the server subscribed before answering the upgrade. Replies to
+        // other callers' commands aren't streamed.
+        for raw in [r#"{"profiles":["A"],"current":"A"}"#, r#"{"p":42}"#] {
+            knob.bus.publish(&Envelope {
+                received_at: Instant::now(),
+                message: Message::try_from(raw).unwrap(),
+                raw: raw.to_string(),
+            });
+        }
+        let mut ws = WebSocket::from_raw_socket(stream, Role::Client, None);
+        let frame = ws.read().unwrap();
+        assert_eq!(frame.to_text().unwrap(), "42");
+
+        // closing ends the stream
+        ws.close(None).unwrap();
+        while ws.read().is_ok() {}
+        for _ in 0..100 {
+            if streams.load(Ordering::SeqCst) == 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(streams.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,558 @@
+use crate::error::Error;
+use crate::protocol::{DeviceError, Heartbeat};
+use crate::{Event, KeyEvent, Message};
+use crossbeam::channel::{bounded, unbounded, Receiver, SendTimeoutError, Sender, TrySendError};
+use log::error;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A parsed message together with when and how it arrived.
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    /// Monotonic time at which the reader thread parsed the line
+    pub received_at: Instant,
+    pub message: Message,
+    /// The line as sent by the device, without the trailing newline
+    pub raw: String,
+}
+
+impl fmt::Display for Envelope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// A line from the device, whether or not it could be parsed.
+#[derive(Debug, Clone)]
+pub struct RawLine {
+    pub received_at: Instant,
+    /// The line as sent by the device, without the trailing newline
+    pub line: String,
+    /// The parsed message, or why parsing failed
+    pub parsed: Result<Message, String>,
+}
+
+impl fmt::Display for RawLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.line)
+    }
+}
+
+/// What to do when a subscriber falls behind and its queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Discard the oldest queued message to make room
+    DropOldest,
+    /// Discard the incoming message
+    DropNewest,
+    /// Discard queued positions in favour of the incoming one, keeping
+    /// everything else. Falls back to dropping the newest message if the
+    /// queue holds nothing but non-position messages.
+    CoalescePositions,
+    /// Wait for the subscriber to catch up. This stalls the reader thread,
+    /// and with it every other subscriber and pending command.
+    Block,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SubscriptionOptions {
+    /// Maximum number of queued messages, at least 1, `None` for unbounded
+    pub capacity: Option<usize>,
+    pub backpressure: Backpressure,
+}
+
+impl Default for SubscriptionOptions {
+    fn default() -> Self {
+        SubscriptionOptions {
+            capacity: Some(1024),
+            backpressure: Backpressure::DropOldest,
+        }
+    }
+}
+
+type Sink<E> = Box<dyn Fn(&E) -> bool + Send>;
+
+/// Fans every envelope out to all subscribers, each with its own channel.
+#[derive(Default)]
+pub(crate) struct Bus {
+    sinks: Mutex<Vec<Sink<Envelope>>>,
+    raw_sinks: Mutex<Vec<Sink<RawLine>>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl fmt::Debug for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Bus")
+            .field("subscribers", &self.sinks.lock().unwrap().len())
+            .field("dropped", &self.dropped())
+            .finish()
+    }
+}
+
+impl Bus {
+    /// Deliver an envelope, removing subscribers that have gone away.
+    pub(crate) fn publish(&self, envelope: &Envelope) {
+        self.sinks.lock().unwrap().retain(|sink| sink(envelope));
+    }
+
+    /// Whether anyone is interested in raw lines, see [`publish_raw`](Bus::publish_raw).
+    pub(crate) fn has_raw_subscribers(&self) -> bool {
+        !self.raw_sinks.lock().unwrap().is_empty()
+    }
+
+    pub(crate) fn publish_raw(&self, line: &RawLine) {
+        self.raw_sinks.lock().unwrap().retain(|sink| sink(line));
+    }
+
+    /// Parse a line and deliver it to raw and message subscribers. Returns
+    /// the envelope, if the line parsed.
+    pub(crate) fn publish_line(&self, line: String, received_at: Instant) -> Option<Envelope> {
+        let parsed = Message::try_from(line.as_str());
+        if self.has_raw_subscribers() {
+            self.publish_raw(&RawLine {
+                received_at,
+                line: line.clone(),
+                parsed: parsed
+                    .as_ref()
+                    .map(Message::clone)
+                    .map_err(Error::to_string),
+            });
+        }
+        match parsed {
+            Ok(message) => {
+                let envelope = Envelope {
+                    received_at,
+                    message,
+                    raw: line,
+                };
+                self.publish(&envelope);
+                Some(envelope)
+            }
+            Err(e) => {
+                error!("could not parse message {:?}: {}", line, e);
+                None
+            }
+        }
+    }
+
+    /// Messages dropped across all subscriptions since the device was opened.
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    closed: AtomicBool,
+    dropped: AtomicU64,
+}
+
+/// A filtered stream of messages from the device.
+///
+/// Derefs to the underlying [`Receiver`], so `recv`, `recv_timeout`, `iter`
+/// and `select!` work as usual. The subscription is removed from the device
+/// when this is dropped or [`unsubscribe`](Subscription::unsubscribe)d.
+pub struct Subscription<T> {
+    receiver: Receiver<T>,
+    state: Arc<State>,
+}
+
+impl<T: Send + 'static> Subscription<T> {
+    fn new<F>(bus: &Bus, options: SubscriptionOptions, map: F, is_position: fn(&T) -> bool) -> Self
+    where
+        F: Fn(&Envelope) -> Option<T> + Send + 'static,
+    {
+        Self::attach(&bus.sinks, bus, options, map, is_position)
+    }
+
+    fn attach<E, F>(
+        sinks: &Mutex<Vec<Sink<E>>>,
+        bus: &Bus,
+        options: SubscriptionOptions,
+        map: F,
+        is_position: fn(&T) -> bool,
+    ) -> Self
+    where
+        E: 'static,
+        F: Fn(&E) -> Option<T> + Send + 'static,
+    {
+        let (tx, rx) = match options.capacity {
+            // a zero capacity channel only hands over to a waiting receiver,
+            // which the reader thread never waits for
+            Some(capacity) => bounded(capacity.max(1)),
+            None => unbounded(),
+        };
+        let state = Arc::new(State::default());
+        let total = bus.dropped.clone();
+
+        let queue = Queue {
+            tx,
+            rx: rx.clone(),
+            state: state.clone(),
+            total,
+            backpressure: options.backpressure,
+            is_position,
+        };
+        sinks.lock().unwrap().push(Box::new(move |item| {
+            if queue.state.closed.load(Ordering::Relaxed) {
+                return false;
+            }
+            match map(item) {
+                Some(value) => queue.push(value),
+                None => true,
+            }
+        }));
+
+        Subscription {
+            receiver: rx,
+            state,
+        }
+    }
+}
+
+impl<T> Subscription<T> {
+    /// Messages this subscription discarded because its queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.state.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Stop receiving messages. Anything already queued is discarded.
+    pub fn unsubscribe(self) {}
+}
+
+impl<T> Deref for Subscription<T> {
+    type Target = Receiver<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.receiver
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        // the sink is removed on the next publish. Taking the bus lock here
+        // could deadlock against a blocked publish.
+        self.state.closed.store(true, Ordering::Relaxed);
+    }
+}
+
+impl<T> fmt::Debug for Subscription<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Subscription")
+            .field("pending", &self.receiver.len())
+            .field("dropped", &self.dropped())
+            .finish()
+    }
+}
+
+/// The sending half of a subscription, applying its backpressure policy.
+struct Queue<T> {
+    tx: Sender<T>,
+    // lets the sender discard queued messages
+    rx: Receiver<T>,
+    state: Arc<State>,
+    total: Arc<AtomicU64>,
+    backpressure: Backpressure,
+    is_position: fn(&T) -> bool,
+}
+
+impl<T> Queue<T> {
+    /// Returns false once the subscriber is gone.
+    fn push(&self, value: T) -> bool {
+        let value = match self.tx.try_send(value) {
+            Ok(()) => return true,
+            Err(TrySendError::Disconnected(_)) => return false,
+            Err(TrySendError::Full(value)) => value,
+        };
+
+        match self.backpressure {
+            Backpressure::DropNewest => self.drop_one(),
+            Backpressure::DropOldest => {
+                if self.rx.try_recv().is_ok() {
+                    self.drop_one();
+                }
+                if self.tx.try_send(value).is_err() {
+                    self.drop_one();
+                }
+            }
+            Backpressure::CoalescePositions => {
+                if (self.is_position)(&value) {
+                    let queued: Vec<T> = self.rx.try_iter().collect();
+                    for queued in queued {
+                        if (self.is_position)(&queued) || self.tx.try_send(queued).is_err() {
+                            self.drop_one();
+                        }
+                    }
+                }
+                if self.tx.try_send(value).is_err() {
+                    self.drop_one();
+                }
+            }
+            Backpressure::Block => {
+                let mut value = value;
+                loop {
+                    match self.tx.send_timeout(value, Duration::from_millis(50)) {
+                        Ok(()) => break,
+                        Err(SendTimeoutError::Disconnected(_)) => return false,
+                        Err(SendTimeoutError::Timeout(v)) => {
+                            if self.state.closed.load(Ordering::Relaxed) {
+                                return false;
+                            }
+                            value = v;
+                        }
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    fn drop_one(&self) {
+        self.state.dropped.fetch_add(1, Ordering::Relaxed);
+        self.total.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Configures and creates a [`Subscription`], see [`Device::subscription`](crate::Device::subscription).
+///
+/// ```no_run
+/// use bongoknob::Backpressure;
+///
+/// # let device: bongoknob::Device = unimplemented!();
+/// let positions = device
+///     .subscription()
+///     .capacity(16)
+///     .backpressure(Backpressure::CoalescePositions)
+///     .positions();
+/// ```
+pub struct SubscriptionBuilder<'a> {
+    bus: &'a Bus,
+    options: SubscriptionOptions,
+}
+
+impl<'a> SubscriptionBuilder<'a> {
+    pub(crate) fn new(bus: &'a Bus, options: SubscriptionOptions) -> Self {
+        SubscriptionBuilder { bus, options }
+    }
+
+    /// Queue at most `capacity` messages. Zero is treated as 1.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.options.capacity = Some(capacity);
+        self
+    }
+
+    pub fn unbounded(mut self) -> Self {
+        self.options.capacity = None;
+        self
+    }
+
+    pub fn backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.options.backpressure = backpressure;
+        self
+    }
+
+    /// Heartbeats and events.
+    pub fn messages(self) -> Subscription<Message> {
+        Subscription::new(
+            self.bus,
+            self.options,
+            |envelope| match envelope.message {
+                Message::Heartbeat(_) | Message::Event(_) => Some(envelope.message.clone()),
+                _ => None,
+            },
+            |message| matches!(message, Message::Event(Event::Position(_))),
+        )
+    }
+
+    /// Every parsed line, including command responses.
+    pub fn envelopes(self) -> Subscription<Envelope> {
+        Subscription::new(
+            self.bus,
+            self.options,
+            |envelope| Some(envelope.clone()),
+            |envelope| matches!(envelope.message, Message::Event(Event::Position(_))),
+        )
+    }
+
+    /// Every line from the device, including ones that failed to parse.
+    pub fn raw_lines(self) -> Subscription<RawLine> {
+        Subscription::attach(
+            &self.bus.raw_sinks,
+            self.bus,
+            self.options,
+            |line: &RawLine| Some(line.clone()),
+            |line| matches!(line.parsed, Ok(Message::Event(Event::Position(_)))),
+        )
+    }
+
+    pub fn positions(self) -> Subscription<u64> {
+        Subscription::new(
+            self.bus,
+            self.options,
+            |envelope| match envelope.message {
+                Message::Event(Event::Position(position)) => Some(position),
+                _ => None,
+            },
+            |_| true,
+        )
+    }
+
+    /// Presses and releases of all keys.
+    pub fn keys(self) -> Subscription<KeyEvent> {
+        Subscription::new(
+            self.bus,
+            self.options,
+            |envelope| match &envelope.message {
+                Message::Event(Event::Key(key)) => Some(key.clone()),
+                _ => None,
+            },
+            |_| false,
+        )
+    }
+
+    /// Presses and releases of a single key.
+    pub fn key(self, id: u8) -> Subscription<KeyEvent> {
+        Subscription::new(
+            self.bus,
+            self.options,
+            move |envelope| match &envelope.message {
+                Message::Event(Event::Key(key)) if key.id() == id => Some(key.clone()),
+                _ => None,
+            },
+            |_| false,
+        )
+    }
+
+    pub fn heartbeats(self) -> Subscription<Heartbeat> {
+        Subscription::new(
+            self.bus,
+            self.options,
+            |envelope| match &envelope.message {
+                Message::Heartbeat(heartbeat) => Some(heartbeat.clone()),
+                _ => None,
+            },
+            |_| false,
+        )
+    }
+
+    /// Errors reported by the device. This includes errors that are also
+    /// returned to the command that caused them.
+    pub fn errors(self) -> Subscription<DeviceError> {
+        Subscription::new(
+            self.bus,
+            self.options,
+            |envelope| match &envelope.message {
+                Message::Error(error) => Some(error.clone()),
+                _ => None,
+            },
+            |_| false,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope(raw: &str) -> Envelope {
+        Envelope {
+            received_at: Instant::now(),
+            message: Message::try_from(raw).unwrap(),
+            raw: raw.to_string(),
+        }
+    }
+
+    fn subscription(bus: &Bus) -> SubscriptionBuilder<'_> {
+        SubscriptionBuilder::new(bus, SubscriptionOptions::default())
+    }
+
+    #[test]
+    fn test_fan_out_and_unsubscribe() {
+        let bus = Bus::default();
+        let positions = subscription(&bus).positions();
+        let heartbeats = subscription(&bus).heartbeats();
+        let everything = subscription(&bus).envelopes();
+
+        bus.publish(&envelope(r#"{"p":12}"#));
+        bus.publish(&envelope(r#"{"idle":1000}"#));
+
+        assert_eq!(positions.try_recv().unwrap(), 12);
+        assert!(positions.try_recv().is_err());
+        assert!(matches!(heartbeats.try_recv().unwrap(), Heartbeat { .. }));
+        assert_eq!(everything.len(), 2);
+
+        positions.unsubscribe();
+        drop(everything);
+        bus.publish(&envelope(r#"{"p":13}"#));
+        assert_eq!(bus.sinks.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_raw_lines() {
+        let bus = Bus::default();
+        assert!(!bus.has_raw_subscribers());
+        let lines = subscription(&bus).raw_lines();
+        assert!(bus.has_raw_subscribers());
+
+        bus.publish_raw(&RawLine {
+            received_at: Instant::now(),
+            line: "garbage".to_string(),
+            parsed: Err("expected value".to_string()),
+        });
+        let line = lines.try_recv().unwrap();
+        assert_eq!(line.to_string(), "garbage");
+        assert!(line.parsed.is_err());
+
+        drop(lines);
+        bus.publish_raw(&RawLine {
+            received_at: Instant::now(),
+            line: String::new(),
+            parsed: Err(String::new()),
+        });
+        assert!(!bus.has_raw_subscribers());
+    }
+
+    #[test]
+    fn test_backpressure() {
+        let bus = Bus::default();
+        let oldest = subscription(&bus)
+            .capacity(2)
+            .backpressure(Backpressure::DropOldest)
+            .positions();
+        let newest = subscription(&bus)
+            .capacity(2)
+            .backpressure(Backpressure::DropNewest)
+            .positions();
+        let coalesced = subscription(&bus)
+            .capacity(2)
+            .backpressure(Backpressure::CoalescePositions)
+            .messages();
+        let zero = subscription(&bus).capacity(0).positions();
+
+        bus.publish(&envelope(r#"{"p":1}"#));
+        bus.publish(&envelope(r#"{"kd":0,"ks":1}"#));
+        bus.publish(&envelope(r#"{"p":2}"#));
+        bus.publish(&envelope(r#"{"p":3}"#));
+
+        assert_eq!(oldest.try_iter().collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(oldest.dropped(), 1);
+        assert_eq!(newest.try_iter().collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(newest.dropped(), 1);
+        assert_eq!(zero.try_iter().collect::<Vec<_>>(), vec![3]);
+        assert_eq!(zero.dropped(), 2);
+
+        let messages: Vec<Message> = coalesced.try_iter().collect();
+        assert!(matches!(
+            messages[..],
+            [
+                Message::Event(Event::Key(KeyEvent::Down { id: 0, .. })),
+                Message::Event(Event::Position(3))
+            ]
+        ));
+        assert_eq!(coalesced.dropped(), 2);
+        assert_eq!(bus.dropped(), 6);
+    }
+}
@@ -0,0 +1,232 @@
+use crate::error::Error;
+use crate::{Command, Event, KeyEvent, Message, Settings};
+use serde_json::{json, Value};
+
+/// Manufacturer ID reserved for non-commercial use.
+const MANUFACTURER: u8 = 0x7d;
+const COMMAND: u8 = 0x01;
+const MESSAGE: u8 = 0x02;
+
+/// Wraps the JSON protocol in System Exclusive messages for when only a MIDI
+/// pipe reaches the knob.
+///
+/// A frame is `F0 7D <device id> <kind> <payload> F7`, where the kind is 1 for
+/// commands and 2 for messages from the device. The payload is the JSON line
+/// the serial port would carry, packed seven bytes at a time behind a byte
+/// holding their high bits. Device ID 127 addresses every device.
+///
+/// This framing is this crate's own and unverified: the firmware doesn't
+/// decode it, so both ends of the MIDI pipe need to use this codec.
+///
+/// ```
+/// use bongoknob::{Command, SysExCodec};
+///
+/// let codec = SysExCodec::new(3);
+/// let frame = codec.encode_command(&Command::GetSettings);
+/// assert_eq!(&frame[..4], [0xf0, 0x7d, 3, 1]);
+/// assert!(matches!(codec.decode_command(&frame)?, Command::GetSettings));
+/// # Ok::<(), bongoknob::Error>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SysExCodec {
+    device_id: u8,
+}
+
+impl SysExCodec {
+    pub const ALL_DEVICES: u8 = 0x7f;
+
+    pub fn new(device_id: u8) -> Self {
+        SysExCodec {
+            device_id: device_id & 0x7f,
+        }
+    }
+
+    /// Address the device with these settings, or every device if it has no ID.
+    pub fn for_settings(settings: &Settings) -> Self {
+        Self::new(settings.sysex_id.unwrap_or(Self::ALL_DEVICES))
+    }
+
+    pub fn device_id(&self) -> u8 {
+        self.device_id
+    }
+
+    pub fn encode_command(&self, command: &Command) -> Vec<u8> {
+        self.frame(COMMAND, &command.to_string())
+    }
+
+    /// Decode a command. Lines other than the settings, profile and save
+    /// commands come back as [`Command::Raw`].
+    pub fn decode_command(&self, frame: &[u8]) -> Result<Command, Error> {
+        let line = self.unframe(COMMAND, frame)?;
+        parse_command(line)
+    }
+
+    pub fn encode_message(&self, message: &Message) -> Result<Vec<u8>, Error> {
+        let line = match message {
+            Message::Event(event) => event_line(event),
+            message => serde_json::to_string(message)?,
+        };
+        Ok(self.frame(MESSAGE, &line))
+    }
+
+    pub fn decode_message(&self, frame: &[u8]) -> Result<Message, Error> {
+        let line = self.unframe(MESSAGE, frame)?;
+        Message::try_from(line.as_str())
+    }
+
+    fn frame(&self, kind: u8, line: &str) -> Vec<u8> {
+        let mut frame = vec![0xf0, MANUFACTURER, self.device_id, kind];
+        for chunk in line.as_bytes().chunks(7) {
+            let high = chunk
+                .iter()
+                .enumerate()
+                .fold(0, |high, (i, b)| high | (b >> 7) << i);
+            frame.push(high);
+            frame.extend(chunk.iter().map(|b| b & 0x7f));
+        }
+        frame.push(0xf7);
+        frame
+    }
+
+    fn unframe(&self, kind: u8, frame: &[u8]) -> Result<String, Error> {
+        let invalid = |reason: &str| Error::ConversionError(format!("invalid SysEx: {}", reason));
+        let [0xf0, MANUFACTURER, device_id, frame_kind, payload @ .., 0xf7] = frame else {
+            return Err(invalid("not a bongoknob frame"));
+        };
+        if *device_id != self.device_id
+            && *device_id != Self::ALL_DEVICES
+            && self.device_id != Self::ALL_DEVICES
+        {
+            return Err(invalid(&format!("addressed to device {}", device_id)));
+        }
+        if *frame_kind != kind {
+            return Err(invalid(&format!("unexpected kind {}", frame_kind)));
+        }
+        if payload.iter().any(|b| b & 0x80 != 0) {
+            return Err(invalid("data byte with the high bit set"));
+        }
+
+        let mut line = Vec::with_capacity(payload.len());
+        for chunk in payload.chunks(8) {
+            let (high, data) = chunk.split_first().ok_or_else(|| invalid("empty chunk"))?;
+            line.extend(
+                data.iter()
+                    .enumerate()
+                    .map(|(i, b)| b | (high >> i & 1) << 7),
+            );
+        }
+        String::from_utf8(line).map_err(|_| invalid("payload is not UTF-8"))
+    }
+}
+
+/// The line the device sends for an event.
+fn event_line(event: &Event) -> String {
+    let (field, key) = match event {
+        Event::Position(position) => return json!({ "p": position }).to_string(),
+        Event::Key(key @ KeyEvent::Down { .. }) => ("kd", key),
+        Event::Key(key @ KeyEvent::Up { .. }) => ("ku", key),
+    };
+    let state = key
+        .keys()
+        .iter()
+        .enumerate()
+        .fold(0, |state, (i, pressed)| state | (*pressed as u8) << i);
+    json!({ field: key.id(), "ks": state }).to_string()
+}
+
+fn parse_command(line: String) -> Result<Command, Error> {
+    let Ok(Value::Object(mut command)) = serde_json::from_str::<Value>(&line) else {
+        return Ok(Command::Raw(line));
+    };
+    let name = |value: Option<&Value>| value.and_then(Value::as_str).map(str::to_string);
+
+    if command.get("profiles").and_then(Value::as_str) == Some("#all") {
+        return Ok(Command::GetProfiles);
+    }
+    if let Some(profile) = name(command.get("profile")) {
+        return Ok(Command::GetProfile(profile));
+    }
+    if let Some(profile) = name(command.get("current")) {
+        return Ok(Command::SetProfile(profile));
+    }
+    match command.remove("settings") {
+        Some(Value::String(query)) if query == "?" => return Ok(Command::GetSettings),
+        Some(settings @ Value::Object(_)) => {
+            return Ok(Command::SetSettings(serde_json::from_value(settings)?))
+        }
+        _ => {}
+    }
+    for (field, parsed) in [
+        ("save", Command::Save),
+        ("load", Command::Load),
+        ("recalibrate", Command::Recalibrate),
+    ] {
+        if command.get(field) == Some(&Value::Bool(true)) {
+            return Ok(parsed);
+        }
+    }
+    Ok(Command::Raw(line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands() {
+        let codec = SysExCodec::new(5);
+        let commands = vec![
+            Command::GetProfiles,
+            Command::GetProfile("Synth".to_string()),
+            Command::SetProfile("Ström".to_string()),
+            Command::GetSettings,
+            Command::SetSettings(Settings {
+                sysex_id: Some(5),
+                device_name: Some("knob".to_string()),
+                ..Default::default()
+            }),
+            Command::Save,
+            Command::Raw(r#"{"screen":{"title":"Hi"}}"#.to_string()),
+        ];
+        for command in commands {
+            let frame = codec.encode_command(&command);
+            assert!(frame[1..frame.len() - 1].iter().all(|b| b & 0x80 == 0));
+            let decoded = codec.decode_command(&frame).unwrap();
+            assert_eq!(decoded.to_string(), command.to_string());
+            assert_eq!(
+                std::mem::discriminant(&decoded),
+                std::mem::discriminant(&command)
+            );
+        }
+    }
+
+    #[test]
+    fn test_messages() {
+        let codec = SysExCodec::new(5);
+        for line in [
+            r#"{"profiles":["A","B"],"current":"B"}"#,
+            r#"{"settings":{"deviceName":"knob","sysexId":5}}"#,
+            r#"{"error":"not found","msg":null}"#,
+            r#"{"p":12}"#,
+            r#"{"ku":3,"ks":1}"#,
+        ] {
+            let message = Message::try_from(line).unwrap();
+            let frame = codec.encode_message(&message).unwrap();
+            let decoded = codec.decode_message(&frame).unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", message));
+        }
+    }
+
+    #[test]
+    fn test_addressing() {
+        let frame = SysExCodec::new(5).encode_command(&Command::Save);
+        assert!(SysExCodec::new(6).decode_command(&frame).is_err());
+        assert!(SysExCodec::new(SysExCodec::ALL_DEVICES)
+            .decode_command(&frame)
+            .is_ok());
+        assert!(SysExCodec::new(5).decode_message(&frame).is_err());
+        assert!(SysExCodec::new(5)
+            .decode_command(&frame[..frame.len() - 1])
+            .is_err());
+    }
+}
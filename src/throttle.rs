@@ -0,0 +1,196 @@
+use crate::subscription::Subscription;
+use crate::{Event, Message};
+use crossbeam::channel::{RecvError, RecvTimeoutError, TryRecvError};
+use std::time::{Duration, Instant};
+
+/// Coalesces `Event::Position` to at most a fixed rate, keeping only the
+/// latest value. Everything else passes through immediately and is never dropped.
+///
+/// A lower rate means less work for the consumer, at the cost of positions
+/// arriving up to one interval late.
+///
+/// ```no_run
+/// # let device: bongoknob::Device = unimplemented!();
+/// // one position per 60Hz frame, keys as they happen
+/// let mut events = bongoknob::Throttled::new(device.subscribe(), 60);
+/// for message in &mut events {
+///     println!("{}", message);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Throttled {
+    inner: Subscription<Message>,
+    interval: Duration,
+    last_emit: Option<Instant>,
+    pending: Option<Message>,
+}
+
+impl Throttled {
+    pub fn new(subscription: Subscription<Message>, max_per_second: u32) -> Self {
+        let interval = Duration::from_secs(1) / max_per_second.max(1);
+        Self::with_interval(subscription, interval)
+    }
+
+    /// Emit at most one position per `interval`.
+    pub fn with_interval(subscription: Subscription<Message>, interval: Duration) -> Self {
+        Throttled {
+            inner: subscription,
+            interval,
+            last_emit: None,
+            pending: None,
+        }
+    }
+
+    pub fn recv(&mut self) -> Result<Message, RecvError> {
+        self.next_until(None).map_err(|_| RecvError)
+    }
+
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Message, RecvTimeoutError> {
+        self.next_until(Some(Instant::now() + timeout))
+    }
+
+    pub fn try_recv(&mut self) -> Result<Message, TryRecvError> {
+        loop {
+            match self.inner.try_recv() {
+                Ok(message) => {
+                    if let Some(message) = self.hold(message) {
+                        return Ok(message);
+                    }
+                }
+                Err(TryRecvError::Disconnected) if self.pending.is_none() => {
+                    return Err(TryRecvError::Disconnected)
+                }
+                Err(_) => break,
+            }
+        }
+
+        let now = Instant::now();
+        match self.due(now) {
+            Some(due) if due <= now => Ok(self.release(now)),
+            _ => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// When the pending position may be emitted, if there is one.
+    fn due(&self, now: Instant) -> Option<Instant> {
+        self.pending.as_ref()?;
+        match self.last_emit {
+            Some(last) => Some(last + self.interval),
+            None => Some(now),
+        }
+    }
+
+    /// Keep positions back, pass anything else on.
+    fn hold(&mut self, message: Message) -> Option<Message> {
+        match message {
+            Message::Event(Event::Position(_)) => {
+                self.pending = Some(message);
+                None
+            }
+            message => Some(message),
+        }
+    }
+
+    fn release(&mut self, now: Instant) -> Message {
+        self.last_emit = Some(now);
+        self.pending.take().unwrap()
+    }
+
+    fn next_until(&mut self, deadline: Option<Instant>) -> Result<Message, RecvTimeoutError> {
+        loop {
+            let now = Instant::now();
+            let due = self.due(now);
+            if let Some(due) = due {
+                if due <= now {
+                    return Ok(self.release(now));
+                }
+            }
+
+            let wake = match (due, deadline) {
+                (Some(due), Some(deadline)) => Some(due.min(deadline)),
+                (due, deadline) => due.or(deadline),
+            };
+            let received = match wake {
+                Some(wake) => self.inner.recv_deadline(wake),
+                None => self
+                    .inner
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            match received {
+                Ok(message) => {
+                    if let Some(message) = self.hold(message) {
+                        return Ok(message);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => match self.pending.take() {
+                    Some(message) => return Ok(message),
+                    None => return Err(RecvTimeoutError::Disconnected),
+                },
+            }
+        }
+    }
+}
+
+impl Iterator for Throttled {
+    type Item = Message;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subscription::{Bus, Envelope, SubscriptionBuilder, SubscriptionOptions};
+    use crate::KeyEvent;
+
+    fn publish(bus: &Bus, raw: &str) {
+        bus.publish(&Envelope {
+            received_at: Instant::now(),
+            message: Message::try_from(raw).unwrap(),
+            raw: raw.to_string(),
+        });
+    }
+
+    #[test]
+    fn test_coalesce_positions() {
+        let bus = Bus::default();
+        let subscription =
+            SubscriptionBuilder::new(&bus, SubscriptionOptions::default()).messages();
+        let mut throttled = Throttled::with_interval(subscription, Duration::from_millis(50));
+
+        publish(&bus, r#"{"p":1}"#);
+        assert!(matches!(
+            throttled.try_recv(),
+            Ok(Message::Event(Event::Position(1)))
+        ));
+
+        // within the interval positions are held back, keys are not
+        publish(&bus, r#"{"p":2}"#);
+        publish(&bus, r#"{"kd":1,"ks":2}"#);
+        publish(&bus, r#"{"p":3}"#);
+        assert!(matches!(
+            throttled.try_recv(),
+            Ok(Message::Event(Event::Key(KeyEvent::Down { id: 1, .. })))
+        ));
+        assert!(matches!(throttled.try_recv(), Err(TryRecvError::Empty)));
+
+        // only the latest position comes through once the interval passed
+        assert!(matches!(
+            throttled.recv_timeout(Duration::from_millis(200)),
+            Ok(Message::Event(Event::Position(3)))
+        ));
+        assert!(matches!(
+            throttled.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        ));
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{Event, Knob, Message};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Clockwise,
+    CounterClockwise,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Direction::Clockwise => write!(f, "CW"),
+            Direction::CounterClockwise => write!(f, "CCW"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrackerEvent {
+    Rotated {
+        /// Absolute position as reported by the device
+        position: u64,
+        /// Signed change since the previous position, unwrapped if the knob wraps
+        delta: i64,
+        /// Smoothed speed in positions per second, signed like `delta`
+        velocity: f64,
+        direction: Direction,
+    },
+}
+
+impl fmt::Display for TrackerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrackerEvent::Rotated {
+                position,
+                delta,
+                velocity,
+                direction,
+            } => write!(
+                f,
+                "[{}] Position: {}, Delta: {:+}, Velocity: {:.1}/s",
+                direction, position, delta, velocity
+            ),
+        }
+    }
+}
+
+/// Turns the absolute `Event::Position` stream into relative motion.
+///
+/// Feed it messages from [`Device::subscribe`](crate::Device::subscribe). When
+/// the active [`Knob`] wraps, jumps across the `value_min`/`value_max` seam are
+/// reported as the short way round instead of a full sweep.
+#[derive(Debug, Clone)]
+pub struct PositionTracker {
+    range: Option<(u64, u64)>,
+    wrap: bool,
+    smoothing: f64,
+    last: Option<(u64, Instant)>,
+    velocity: f64,
+}
+
+impl Default for PositionTracker {
+    fn default() -> Self {
+        PositionTracker {
+            range: None,
+            wrap: false,
+            smoothing: 0.5,
+            last: None,
+            velocity: 0.0,
+        }
+    }
+}
+
+impl PositionTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_knob(knob: &Knob) -> Self {
+        let mut tracker = Self::default();
+        tracker.set_knob(knob);
+        tracker
+    }
+
+    /// Apply the range and wrap configuration of a knob, e.g. after switching profiles.
+    pub fn set_knob(&mut self, knob: &Knob) {
+        let (min, max) = (knob.value_min as u64, knob.value_max as u64);
+        self.range = Some((min.min(max), min.max(max)));
+        self.wrap = knob.wrap;
+    }
+
+    /// Weight given to the previous velocity estimate, from 0.0 (raw) to just below 1.0.
+    pub fn set_smoothing(&mut self, smoothing: f64) {
+        self.smoothing = smoothing.clamp(0.0, 0.99);
+    }
+
+    pub fn position(&self) -> Option<u64> {
+        self.last.map(|(position, _)| position)
+    }
+
+    pub fn velocity(&self) -> f64 {
+        self.velocity
+    }
+
+    /// Forget the last position so the next one does not produce a delta.
+    pub fn reset(&mut self) {
+        self.last = None;
+        self.velocity = 0.0;
+    }
+
+    /// Process a message received from the device, ignoring anything but positions.
+    pub fn handle(&mut self, message: &Message) -> Option<TrackerEvent> {
+        match message {
+            Message::Event(Event::Position(position)) => self.update(*position, Instant::now()),
+            _ => None,
+        }
+    }
+
+    /// Record a position observed at `at`. The first position only sets the
+    /// baseline, and positions that didn't move produce no event.
+    pub fn update(&mut self, position: u64, at: Instant) -> Option<TrackerEvent> {
+        let (last, last_at) = self.last.replace((position, at))?;
+
+        let delta = self.delta(last, position);
+        if delta == 0 {
+            return None;
+        }
+
+        let elapsed = at
+            .saturating_duration_since(last_at)
+            .max(Duration::from_micros(100));
+        let instant = delta as f64 / elapsed.as_secs_f64();
+        // don't let the old direction bleed into a reversal
+        self.velocity = if self.velocity != 0.0 && instant.signum() == self.velocity.signum() {
+            self.smoothing * self.velocity + (1.0 - self.smoothing) * instant
+        } else {
+            instant
+        };
+
+        let direction = if delta > 0 {
+            Direction::Clockwise
+        } else {
+            Direction::CounterClockwise
+        };
+
+        Some(TrackerEvent::Rotated {
+            position,
+            delta,
+            velocity: self.velocity,
+            direction,
+        })
+    }
+
+    fn delta(&self, from: u64, to: u64) -> i64 {
+        let delta = to as i64 - from as i64;
+        match self.range {
+            Some((min, max)) if self.wrap => {
+                let span = (max - min + 1) as i64;
+                if delta > span / 2 {
+                    delta - span
+                } else if delta < -span / 2 {
+                    delta + span
+                } else {
+                    delta
+                }
+            }
+            _ => delta,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Haptic;
+
+    fn knob(value_min: u8, value_max: u8, wrap: bool) -> Knob {
+        Knob {
+            value_min,
+            value_max,
+            angle_min: 0,
+            angle_max: 0,
+            wrap,
+            step: 1,
+            key_state: 0,
+            haptic: Haptic {
+                mode: 0,
+                start_pos: 0,
+                end_pos: 0,
+                detent_count: 0,
+                vernier: 0,
+                kx_force: false,
+                output_ramp: 0,
+                detent_strength: 0,
+            },
+            knob_type: "cc".to_string(),
+            channel: 1,
+            cc: 1,
+        }
+    }
+
+    #[test]
+    fn test_delta_and_direction() {
+        let mut tracker = PositionTracker::new();
+        let start = Instant::now();
+
+        assert_eq!(tracker.update(10, start), None);
+        assert_eq!(tracker.update(10, start + Duration::from_millis(5)), None);
+
+        match tracker.update(13, start + Duration::from_millis(10)) {
+            Some(TrackerEvent::Rotated {
+                delta, direction, ..
+            }) => {
+                assert_eq!(delta, 3);
+                assert_eq!(direction, Direction::Clockwise);
+            }
+            e => panic!("unexpected event {:?}", e),
+        }
+
+        match tracker.update(11, start + Duration::from_millis(20)) {
+            Some(TrackerEvent::Rotated {
+                delta,
+                direction,
+                velocity,
+                ..
+            }) => {
+                assert_eq!(delta, -2);
+                assert_eq!(direction, Direction::CounterClockwise);
+                assert!(velocity < 0.0);
+            }
+            e => panic!("unexpected event {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_wrap() {
+        let mut tracker = PositionTracker::with_knob(&knob(0, 127, true));
+        let start = Instant::now();
+
+        tracker.update(126, start);
+        match tracker.update(1, start + Duration::from_millis(10)) {
+            Some(TrackerEvent::Rotated { delta, .. }) => assert_eq!(delta, 3),
+            e => panic!("unexpected event {:?}", e),
+        }
+        match tracker.update(127, start + Duration::from_millis(20)) {
+            Some(TrackerEvent::Rotated { delta, .. }) => assert_eq!(delta, -2),
+            e => panic!("unexpected event {:?}", e),
+        }
+
+        // without wrap the same jump is a full sweep
+        let mut tracker = PositionTracker::with_knob(&knob(0, 127, false));
+        tracker.update(126, start);
+        match tracker.update(1, start + Duration::from_millis(10)) {
+            Some(TrackerEvent::Rotated { delta, .. }) => assert_eq!(delta, -125),
+            e => panic!("unexpected event {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,365 @@
+use crate::error::Error;
+use crate::knob_device::KnobDevice;
+use crate::Profile;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// A haptic value that can be tuned in a [`TuningSession`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TuningParameter {
+    /// `Haptic::detent_strength` of the tuned knob
+    DetentStrength,
+    FeedbackStrength,
+    BounceStrength,
+    AttractDistance,
+    HapticClickStrength,
+}
+
+impl TuningParameter {
+    pub const ALL: [TuningParameter; 5] = [
+        TuningParameter::DetentStrength,
+        TuningParameter::FeedbackStrength,
+        TuningParameter::BounceStrength,
+        TuningParameter::AttractDistance,
+        TuningParameter::HapticClickStrength,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TuningParameter::DetentStrength => "detent_strength",
+            TuningParameter::FeedbackStrength => "feedback_strength",
+            TuningParameter::BounceStrength => "bounce_strength",
+            TuningParameter::AttractDistance => "attract_distance",
+            TuningParameter::HapticClickStrength => "haptic_click_strength",
+        }
+    }
+
+    pub fn max(&self) -> u32 {
+        match self {
+            TuningParameter::DetentStrength => u8::MAX as u32,
+            _ => u32::MAX,
+        }
+    }
+}
+
+impl fmt::Display for TuningParameter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for TuningParameter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.replace('-', "_");
+        Self::ALL
+            .into_iter()
+            .find(|p| p.name().eq_ignore_ascii_case(&s))
+            .ok_or_else(|| Error::ConversionError(format!("unknown tuning parameter `{}`", s)))
+    }
+}
+
+/// Live tuning of the haptic feel of one knob of a profile.
+///
+/// Changes are collected and handed out once they settle for the debounce
+/// interval, so a parameter can be swept with the knob itself without
+/// flooding the device. [`save`](TuningSession::save) persists the result,
+/// [`revert`](TuningSession::revert) restores the profile as it was. Only
+/// parameters the profile sets can be tuned, so there is always a value to
+/// go back to.
+///
+/// The firmware has no known command for writing a profile, so the updates
+/// from [`poll`](TuningSession::poll) are left to the caller to apply.
+///
+/// ```no_run
+/// use bongoknob::{TuningParameter, TuningSession};
+/// use std::time::Instant;
+///
+/// # let device: bongoknob::Device = unimplemented!();
+/// # let apply = |_: bongoknob::Profile| ();
+/// let profile = device.get_profile("GRASSY HOPPER")?;
+/// let mut session = TuningSession::new("GRASSY HOPPER", profile, 0)?;
+/// session.adjust(TuningParameter::DetentStrength, 2)?;
+/// if let Some(updates) = session.poll(Instant::now()) {
+///     apply(updates);
+/// }
+/// session.save(&device)?;
+/// # Ok::<(), bongoknob::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct TuningSession {
+    name: String,
+    knob: usize,
+    original: Profile,
+    current: Profile,
+    uploaded: Profile,
+    debounce: Duration,
+    changed_at: Option<Instant>,
+}
+
+impl TuningSession {
+    /// Tune knob `knob` of the profile called `name`.
+    pub fn new(name: &str, profile: Profile, knob: usize) -> Result<Self, Error> {
+        if profile.knob.as_ref().map_or(0, |k| k.len()) <= knob {
+            return Err(Error::InvalidConfig(format!(
+                "profile has no knob {}",
+                knob
+            )));
+        }
+        Ok(TuningSession {
+            name: name.to_string(),
+            knob,
+            original: profile.clone(),
+            current: profile.clone(),
+            uploaded: profile,
+            debounce: Duration::from_millis(150),
+            changed_at: None,
+        })
+    }
+
+    /// How long changes must settle before they are handed out. Defaults to 150ms.
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.current
+    }
+
+    /// The current value, or `None` if the profile doesn't set it.
+    pub fn get(&self, parameter: TuningParameter) -> Option<u32> {
+        get(&self.current, self.knob, parameter)
+    }
+
+    /// Set a value, clamped to what the parameter can hold. Parameters the
+    /// profile doesn't set are an error.
+    pub fn set(&mut self, parameter: TuningParameter, value: u32) -> Result<(), Error> {
+        self.set_at(parameter, value, Instant::now())
+    }
+
+    pub fn set_at(
+        &mut self,
+        parameter: TuningParameter,
+        value: u32,
+        now: Instant,
+    ) -> Result<(), Error> {
+        if get(&self.original, self.knob, parameter).is_none() {
+            return Err(Error::InvalidConfig(format!(
+                "profile doesn't set {}",
+                parameter
+            )));
+        }
+        let value = value.min(parameter.max());
+        if Some(value) == self.get(parameter) {
+            return Ok(());
+        }
+        let profile = &mut self.current;
+        match parameter {
+            TuningParameter::DetentStrength => {
+                profile.knob.as_mut().unwrap()[self.knob]
+                    .haptic
+                    .detent_strength = value as u8
+            }
+            TuningParameter::FeedbackStrength => profile.feedback_strength = Some(value),
+            TuningParameter::BounceStrength => profile.bounce_strength = Some(value),
+            TuningParameter::AttractDistance => profile.attract_distance = Some(value),
+            TuningParameter::HapticClickStrength => profile.haptic_click_strength = Some(value),
+        }
+        self.changed_at = Some(now);
+        Ok(())
+    }
+
+    /// Change a value by `delta`, saturating at its limits.
+    pub fn adjust(&mut self, parameter: TuningParameter, delta: i64) -> Result<(), Error> {
+        let value = (self.get(parameter).unwrap_or(0) as i64 + delta).clamp(0, u32::MAX as i64);
+        self.set(parameter, value as u32)
+    }
+
+    /// Whether anything differs from the profile the session started with.
+    pub fn is_modified(&self) -> bool {
+        TuningParameter::ALL
+            .iter()
+            .any(|p| get(&self.current, self.knob, *p) != get(&self.original, self.knob, *p))
+    }
+
+    /// The profile name and the fields that changed, once changes have
+    /// settled at `now`.
+    pub fn poll(&mut self, now: Instant) -> Option<Profile> {
+        match self.changed_at {
+            Some(at) if now >= at + self.debounce => self.flush(),
+            _ => None,
+        }
+    }
+
+    /// Hand out pending changes right away.
+    pub fn flush(&mut self) -> Option<Profile> {
+        self.changed_at = None;
+        let updates = self.changes();
+        self.uploaded = self.current.clone();
+        updates
+    }
+
+    /// Save the profile on the device. Once the device confirms, the values
+    /// handed out so far become the new starting point for
+    /// [`revert`](TuningSession::revert).
+    pub fn save(&mut self, device: &impl KnobDevice) -> Result<(), Error> {
+        device.save_settings()?;
+        self.original = self.uploaded.clone();
+        Ok(())
+    }
+
+    /// Restore the values the session started with, returning the fields to
+    /// change back.
+    pub fn revert(&mut self) -> Option<Profile> {
+        self.current = self.original.clone();
+        self.flush()
+    }
+
+    /// Values of `current` that haven't been handed out yet, along with the
+    /// profile name.
+    fn changes(&self) -> Option<Profile> {
+        let (current, uploaded) = (&self.current, &self.uploaded);
+        let mut updates = Profile {
+            name: Some(self.name.clone()),
+            ..Default::default()
+        };
+        let mut changed = false;
+        for parameter in TuningParameter::ALL {
+            if get(current, self.knob, parameter) == get(uploaded, self.knob, parameter) {
+                continue;
+            }
+            let Some(value) = get(current, self.knob, parameter) else {
+                continue;
+            };
+            changed = true;
+            let value = Some(value);
+            match parameter {
+                TuningParameter::DetentStrength => updates.knob = current.knob.clone(),
+                TuningParameter::FeedbackStrength => updates.feedback_strength = value,
+                TuningParameter::BounceStrength => updates.bounce_strength = value,
+                TuningParameter::AttractDistance => updates.attract_distance = value,
+                TuningParameter::HapticClickStrength => updates.haptic_click_strength = value,
+            }
+        }
+        changed.then_some(updates)
+    }
+}
+
+fn get(profile: &Profile, knob: usize, parameter: TuningParameter) -> Option<u32> {
+    match parameter {
+        TuningParameter::DetentStrength => profile
+            .knob
+            .as_ref()
+            .map(|k| k[knob].haptic.detent_strength as u32),
+        TuningParameter::FeedbackStrength => profile.feedback_strength,
+        TuningParameter::BounceStrength => profile.bounce_strength,
+        TuningParameter::AttractDistance => profile.attract_distance,
+        TuningParameter::HapticClickStrength => profile.haptic_click_strength,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::knob_device::fake::FakeKnob;
+    use crate::{Command, Haptic, Knob};
+
+    fn session() -> TuningSession {
+        let knob = Knob {
+            value_min: 0,
+            value_max: 11,
+            angle_min: 0,
+            angle_max: 255,
+            wrap: true,
+            step: 1,
+            key_state: 0,
+            haptic: Haptic {
+                mode: 0,
+                start_pos: 0,
+                end_pos: 255,
+                detent_count: 12,
+                vernier: 0,
+                kx_force: false,
+                output_ramp: 10000,
+                detent_strength: 6,
+            },
+            knob_type: "cc".to_string(),
+            channel: 1,
+            cc: 1,
+        };
+        let profile = Profile {
+            feedback_strength: Some(5),
+            bounce_strength: Some(1),
+            attract_distance: Some(4),
+            knob: Some(vec![knob]),
+            ..Default::default()
+        };
+        TuningSession::new("test", profile, 0).unwrap()
+    }
+
+    #[test]
+    fn test_debounced_uploads() {
+        let mut session = session();
+        let start = Instant::now();
+        assert!(TuningSession::new("test", Profile::default(), 0).is_err());
+        assert!(TuningSession::new("test", session.profile().clone(), 1).is_err());
+
+        session
+            .set_at(TuningParameter::FeedbackStrength, 6, start)
+            .unwrap();
+        session
+            .set_at(
+                TuningParameter::FeedbackStrength,
+                7,
+                start + Duration::from_millis(100),
+            )
+            .unwrap();
+        assert!(session.poll(start + Duration::from_millis(200)).is_none());
+
+        let profile = session.poll(start + Duration::from_millis(250)).unwrap();
+        assert_eq!(profile.name.as_deref(), Some("test"));
+        assert_eq!(profile.feedback_strength, Some(7));
+        assert!(profile.knob.is_none());
+        assert!(session.poll(start + Duration::from_secs(1)).is_none());
+
+        session
+            .adjust(TuningParameter::DetentStrength, 1000)
+            .unwrap();
+        assert_eq!(session.get(TuningParameter::DetentStrength), Some(255));
+        let profile = session.flush().unwrap();
+        assert_eq!(profile.knob.unwrap()[0].haptic.detent_strength, 255);
+        assert_eq!(profile.feedback_strength, None);
+    }
+
+    #[test]
+    fn test_save_and_revert() {
+        let mut session = session();
+        // nothing to revert to
+        assert!(session
+            .set(TuningParameter::HapticClickStrength, 2)
+            .is_err());
+
+        session.set(TuningParameter::BounceStrength, 3).unwrap();
+        session.flush();
+        // the save isn't confirmed
+        assert!(session.save(&FakeKnob::default()).is_err());
+        assert!(session.is_modified());
+        let knob = FakeKnob::default().reply(Command::Save, r#"{"saved":true}"#);
+        session.save(&knob).unwrap();
+        assert!(!session.is_modified());
+
+        session.set(TuningParameter::BounceStrength, 9).unwrap();
+        session.set(TuningParameter::AttractDistance, 2).unwrap();
+        assert!(session.is_modified());
+        session.flush();
+
+        let profile = session.revert().unwrap();
+        assert_eq!(profile.bounce_strength, Some(3));
+        assert_eq!(profile.attract_distance, Some(4));
+        assert_eq!(session.get(TuningParameter::AttractDistance), Some(4));
+        assert!(!session.is_modified());
+    }
+}